@@ -0,0 +1,280 @@
+//! Coin sounds. There are no audio assets yet, so every sound is synthesized
+//! once at startup into a small WAV buffer.
+//!
+//! One-shots (clinks, landings, squelches) are spawned where they happen and
+//! despawn when done. The roll and jump charge sounds loop for the whole game
+//! and just get their volume and pitch changed.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use bevy::audio::{AudioSinkPlayback, Volume};
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::game_const::*;
+use crate::player::{Controlled, Face, GroundState, JumpStrength, Player, PlayerLanded};
+use crate::settings::Settings;
+use crate::testmap::Sticky;
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SoundBank>()
+            .add_systems(Startup, spawn_loops)
+            .add_systems(Update, (
+                collision_sounds,
+                landing_sounds,
+                roll_sound,
+                jump_charge_sound,
+            ))
+            ;
+    }
+}
+
+const SAMPLE_RATE: u32 = 44100;
+// impulse at which a clink plays at full volume
+const CLINK_FULL_IMPULSE: f32 = 20.0;
+// angular speed at which the roll sound is at full volume and pitch 2x
+const ROLL_FULL_SPEED: f32 = 20.0;
+// gap between the two ears of the listener
+const EAR_GAP: f32 = 0.4;
+
+#[derive(Resource)]
+pub struct SoundBank {
+    pub clink: Handle<AudioSource>,
+    pub roll: Handle<AudioSource>,
+    pub thud_heads: Handle<AudioSource>,
+    pub thud_tails: Handle<AudioSource>,
+    pub squelch: Handle<AudioSource>,
+    pub whine: Handle<AudioSource>,
+}
+
+impl FromWorld for SoundBank {
+    fn from_world(world: &mut World) -> Self {
+        let mut sources = world.resource_mut::<Assets<AudioSource>>();
+        Self{
+            clink: sources.add(wav_source(&clink_samples())),
+            roll: sources.add(wav_source(&roll_samples())),
+            thud_heads: sources.add(wav_source(&thud_samples(90.0))),
+            thud_tails: sources.add(wav_source(&thud_samples(70.0))),
+            squelch: sources.add(wav_source(&squelch_samples())),
+            whine: sources.add(wav_source(&whine_samples())),
+        }
+    }
+}
+
+#[derive(Component)]
+struct RollSound;
+
+#[derive(Component)]
+struct JumpChargeSound;
+
+fn spawn_loops(mut commands: Commands, sounds: Res<SoundBank>) {
+    commands.spawn((RollSound, SpatialAudioBundle {
+        source: sounds.roll.clone(),
+        settings: PlaybackSettings::LOOP.with_volume(Volume::new_relative(0.0)),
+        spatial: SpatialSettings::new(Transform::IDENTITY, EAR_GAP, Vec3::ZERO),
+    }));
+    commands.spawn((JumpChargeSound, SpatialAudioBundle {
+        source: sounds.whine.clone(),
+        settings: PlaybackSettings::LOOP.with_volume(Volume::new_relative(0.0)),
+        spatial: SpatialSettings::new(Transform::IDENTITY, EAR_GAP, Vec3::ZERO),
+    }));
+}
+
+fn listener_transform(cameras: &Query<&GlobalTransform, With<Camera3d>>) -> Transform {
+    cameras.iter().next().map(|t| t.compute_transform()).unwrap_or_default()
+}
+
+fn play_at(
+    commands: &mut Commands,
+    sound: &Handle<AudioSource>,
+    position: Vec3,
+    listener: Transform,
+    volume: f32,
+    speed: f32,
+) {
+    commands.spawn(SpatialAudioBundle {
+        source: sound.clone(),
+        settings: PlaybackSettings::DESPAWN
+            .with_volume(Volume::new_relative(volume))
+            .with_speed(speed),
+        spatial: SpatialSettings::new(listener, EAR_GAP, position),
+    });
+}
+
+fn collision_sounds(
+    mut commands: Commands,
+    mut started: EventReader<CollisionStarted>,
+    sounds: Res<SoundBank>,
+    settings: Res<Settings>,
+    players: Query<(&GlobalTransform, &LinearVelocity, &Mass), With<Player>>,
+    sticky: Query<(), With<Sticky>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    let listener = listener_transform(&cameras);
+    for CollisionStarted(e1, e2) in started.iter() {
+        for (player, other) in [(*e1, *e2), (*e2, *e1)] {
+            let Ok((transform, velocity, mass)) = players.get(player) else { continue };
+            if sticky.contains(other) {
+                play_at(&mut commands, &sounds.squelch, transform.translation(), listener, settings.sfx_volume, 1.0);
+                continue;
+            }
+            // no impulses from the solver here, mass times speed is close enough for a clink
+            let impulse = mass.0 * velocity.length();
+            let volume = (impulse / CLINK_FULL_IMPULSE).clamp(0.0, 1.0);
+            if volume > 0.05 {
+                let speed = 0.9 + 0.2 * volume;
+                play_at(&mut commands, &sounds.clink, transform.translation(), listener, volume * settings.sfx_volume, speed);
+            }
+        }
+    }
+}
+
+fn landing_sounds(
+    mut commands: Commands,
+    mut landed: EventReader<PlayerLanded>,
+    sounds: Res<SoundBank>,
+    settings: Res<Settings>,
+    players: Query<&GlobalTransform, With<Player>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    let listener = listener_transform(&cameras);
+    for event in landed.iter() {
+        let Ok(transform) = players.get(event.player) else { continue };
+        let sound = match event.face {
+            Face::Heads => &sounds.thud_heads,
+            Face::Tails => &sounds.thud_tails,
+        };
+        let volume = (event.impact_speed / 10.0).clamp(0.2, 1.0);
+        play_at(&mut commands, sound, transform.translation(), listener, volume * settings.sfx_volume, 1.0);
+    }
+}
+
+fn roll_sound(
+    settings: Res<Settings>,
+    players: Query<(&Controlled, &GlobalTransform, &AngularVelocity, &GroundState, &CollidingEntities), With<Player>>,
+    sensors: Query<(), With<Sensor>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    sinks: Query<&SpatialAudioSink, With<RollSound>>,
+) {
+    let Ok(sink) = sinks.get_single() else { return };
+    // there's one roll loop, it follows the first seat's coin
+    let Some((_, transform, angular_velocity, ground, colliding)) = players.iter().min_by_key(|(controlled, ..)| controlled.seat) else {
+        sink.set_volume(0.0);
+        return;
+    };
+    // rolling on the rim means touching something solid while neither face
+    // is on the ground, spinning through the air doesn't count
+    let touching = colliding.iter().any(|entity| !sensors.contains(*entity));
+    let on_rim = touching && !ground.grounded();
+    let speed = (angular_velocity.length() / ROLL_FULL_SPEED).clamp(0.0, 1.0);
+    let volume = if on_rim { speed } else { 0.0 };
+
+    sink.set_listener_position(listener_transform(&cameras), EAR_GAP);
+    sink.set_emitter_position(transform.translation());
+    sink.set_volume(volume * settings.sfx_volume * settings.master_volume);
+    sink.set_speed(1.0 + speed);
+}
+
+fn jump_charge_sound(
+    settings: Res<Settings>,
+    players: Query<(&Controlled, &GlobalTransform, &JumpStrength)>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    sinks: Query<&SpatialAudioSink, With<JumpChargeSound>>,
+) {
+    let Ok(sink) = sinks.get_single() else { return };
+    // only a played coin charges, the loop follows the first seat's
+    let Some((_, transform, jump_strength)) = players.iter().min_by_key(|(controlled, ..)| controlled.seat) else {
+        sink.pause();
+        return;
+    };
+    let charge = jump_strength.0 / MAX_JUMP_TIME_LENGTH;
+    if charge <= 0.0 {
+        sink.pause();
+        return;
+    }
+    sink.set_emitter_position(transform.translation());
+    sink.set_listener_position(listener_transform(&cameras), EAR_GAP);
+    sink.set_volume(0.5 * settings.sfx_volume * settings.master_volume);
+    sink.set_speed(1.0 + charge);
+    sink.play();
+}
+
+
+fn wav_source(samples: &[f32]) -> AudioSource {
+    AudioSource { bytes: Arc::from(wav_bytes(samples)) }
+}
+
+/// 16 bit mono PCM.
+fn wav_bytes(samples: &[f32]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+fn synth(seconds: f32, f: impl Fn(f32) -> f32) -> Vec<f32> {
+    let count = (seconds * SAMPLE_RATE as f32) as usize;
+    (0..count).map(|i| f(i as f32 / SAMPLE_RATE as f32)).collect()
+}
+
+/// Cheap deterministic noise, good enough for rumble and squelch.
+fn noise(t: f32) -> f32 {
+    ((t * SAMPLE_RATE as f32 * 12.9898).sin() * 43758.547).fract() * 2.0 - 1.0
+}
+
+fn clink_samples() -> Vec<f32> {
+    // a few inharmonic partials sound metallic
+    synth(0.4, |t| {
+        let partials = [(2100.0, 1.0), (3320.0, 0.6), (5070.0, 0.35), (7400.0, 0.2)];
+        let tone: f32 = partials.iter().map(|(f, a)| a * (2.0 * PI * f * t).sin()).sum();
+        0.3 * tone * (-12.0 * t).exp()
+    })
+}
+
+fn roll_samples() -> Vec<f32> {
+    // loops cleanly since 1 second holds whole periods of both tones
+    synth(1.0, |t| {
+        let rumble = 0.5 * (2.0 * PI * 60.0 * t).sin() + 0.3 * (2.0 * PI * 97.0 * t).sin();
+        0.4 * (rumble + 0.2 * noise(t))
+    })
+}
+
+fn thud_samples(pitch: f32) -> Vec<f32> {
+    synth(0.3, |t| {
+        let f = pitch * (1.0 - 0.5 * t);
+        0.8 * (2.0 * PI * f * t).sin() * (-15.0 * t).exp()
+    })
+}
+
+fn squelch_samples() -> Vec<f32> {
+    synth(0.35, |t| {
+        let f = 400.0 - 800.0 * t;
+        0.5 * ((2.0 * PI * f * t).sin() * 0.6 + noise(t) * 0.4) * (-8.0 * t).exp()
+    })
+}
+
+fn whine_samples() -> Vec<f32> {
+    synth(1.0, |t| {
+        0.25 * ((2.0 * PI * 880.0 * t).sin() + 0.3 * (2.0 * PI * 1760.0 * t).sin())
+    })
+}
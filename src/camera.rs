@@ -0,0 +1,247 @@
+//! Camera modes. The camera spawned with the player carries a `CameraRig`
+//! which puts it where its mode wants it every frame. Switching modes blends
+//! from wherever the camera was, so cuts never snap.
+//!
+//! The free-fly camera ignores colliders, which makes it the easiest way to
+//! look inside closed off geometry like the secret room.
+
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+
+use crate::AppState;
+use crate::console::ConsoleAppExt;
+use crate::game_const::*;
+use crate::player::{CoinInput, Controlled, ControlsLocked};
+use crate::settings::{Action, KeyBindings, Settings};
+
+pub struct CameraRigPlugin;
+
+impl Plugin for CameraRigPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, cycle_camera_mode.run_if(in_state(AppState::InGame)))
+            .add_systems(Update, camera_look.run_if(in_state(AppState::InGame).or_else(in_state(AppState::Editor))))
+            .add_systems(Update, update_camera_rig.after(camera_look))
+            .add_console_command("noclip", "noclip: toggles the free-fly camera", noclip)
+            ;
+    }
+}
+
+// the follow camera can't go under the floor or straight overhead
+const FOLLOW_PITCH_MIN: f32 = -1.1;
+const FOLLOW_PITCH_MAX: f32 = 0.4;
+const FLY_PITCH_LIMIT: f32 = 1.5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CameraMode {
+    /// Behind the coin, turned with the mouse.
+    Follow,
+    /// Slowly circles the coin, for the level complete screen.
+    Orbit,
+    /// Straight down from above, for puzzle rooms.
+    TopDown,
+    /// Flies anywhere with the movement keys, through walls.
+    FreeFly,
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            CameraMode::Follow => CameraMode::TopDown,
+            CameraMode::TopDown => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Follow,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct CameraRig {
+    /// Whose coin this camera follows, see `Controlled`.
+    pub seat: usize,
+    pub mode: CameraMode,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fly_position: Vec3,
+    blend_from: Transform,
+    /// 0 right after a mode change, 1 once the blend is done.
+    blend: f32,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self{
+            seat: 0,
+            mode: CameraMode::Follow,
+            yaw: 0.0,
+            pitch: -0.3,
+            fly_position: Vec3::ZERO,
+            blend_from: Transform::IDENTITY,
+            blend: 1.0,
+        }
+    }
+}
+
+impl CameraRig {
+    pub fn for_seat(seat: usize) -> Self {
+        Self{seat, ..default()}
+    }
+
+    /// Switches modes, blending from `current`, the camera's transform right now.
+    pub fn set_mode(&mut self, mode: CameraMode, current: Transform) {
+        if mode == self.mode {
+            return;
+        }
+        if mode == CameraMode::FreeFly {
+            // take off from where the camera already is
+            let (yaw, pitch, _) = current.rotation.to_euler(EulerRot::YXZ);
+            self.fly_position = current.translation;
+            self.yaw = yaw;
+            self.pitch = pitch;
+        }
+        self.mode = mode;
+        self.blend_from = current;
+        self.blend = 0.0;
+    }
+
+    fn look_rotation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+    }
+
+    /// Where the camera should be this frame, ignoring blending and smoothing.
+    fn target(&self, player: Vec3) -> Transform {
+        match self.mode {
+            CameraMode::Follow => {
+                Transform::from_translation(player + self.look_rotation() * CAMERA_RELATIVE)
+                    .looking_at(player + CAMERA_LOOK, Vec3::Y)
+            }
+            CameraMode::Orbit => {
+                let offset = Quat::from_rotation_y(self.yaw) * Vec3::new(0.0, 0.4, 1.0) * CAMERA_ORBIT_DISTANCE;
+                Transform::from_translation(player + offset).looking_at(player, Vec3::Y)
+            }
+            CameraMode::TopDown => {
+                Transform::from_translation(player + Vec3::Y * CAMERA_TOP_DOWN_HEIGHT)
+                    .looking_at(player, Vec3::NEG_Z)
+            }
+            CameraMode::FreeFly => {
+                Transform::from_translation(self.fly_position).with_rotation(self.look_rotation())
+            }
+        }
+    }
+}
+
+fn cycle_camera_mode(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut locked: ResMut<ControlsLocked>,
+    mut cameras: Query<(&Transform, &mut CameraRig)>,
+) {
+    if !bindings.just_pressed(Action::CameraMode, &keyboard_input) {
+        return;
+    }
+    for (transform, mut rig) in cameras.iter_mut().filter(|(_, rig)| rig.seat == 0) {
+        let next = rig.mode.next();
+        rig.set_mode(next, *transform);
+        // the free-fly camera takes over the movement keys
+        locked.0 = next == CameraMode::FreeFly;
+    }
+}
+
+fn noclip(world: &mut World, _args: &[&str]) -> Result<String, String> {
+    let mut cameras = world.query::<(&Transform, &mut CameraRig)>();
+    let mut flying = false;
+    for (transform, mut rig) in cameras.iter_mut(world) {
+        let mode = if rig.mode == CameraMode::FreeFly { CameraMode::Follow } else { CameraMode::FreeFly };
+        rig.set_mode(mode, *transform);
+        flying = mode == CameraMode::FreeFly;
+    }
+    // the console restores its own lock when it closes, so set what it restores to
+    world.resource_mut::<crate::console::Console>().set_locked_on_close(flying);
+    Ok(format!("noclip {}", if flying { "on" } else { "off" }))
+}
+
+/// The mouse turns seat 0's camera, a right stick turns the camera of the
+/// seat its gamepad belongs to.
+fn camera_look(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut motion: EventReader<MouseMotion>,
+    players: Query<(&CoinInput, &Controlled)>,
+    mut cameras: Query<&mut CameraRig>,
+) {
+    let mouse = motion.iter().map(|event| event.delta).sum::<Vec2>() * settings.mouse_scale();
+    let dt = time.delta_seconds();
+    for mut rig in cameras.iter_mut() {
+        let stick = players.iter()
+            .find(|(_, controlled)| controlled.seat == rig.seat)
+            .map_or(Vec2::ZERO, |(input, _)| input.look);
+        let mut turn = Vec2::new(stick.x, -stick.y) * CAMERA_STICK_SPEED * dt;
+        if rig.seat == 0 {
+            turn += mouse;
+        }
+        if turn == Vec2::ZERO {
+            continue;
+        }
+        rig.yaw -= turn.x;
+        rig.pitch -= turn.y;
+        rig.pitch = match rig.mode {
+            CameraMode::FreeFly => rig.pitch.clamp(-FLY_PITCH_LIMIT, FLY_PITCH_LIMIT),
+            _ => rig.pitch.clamp(FOLLOW_PITCH_MIN, FOLLOW_PITCH_MAX),
+        };
+    }
+}
+
+fn update_camera_rig(
+    time: Res<Time>,
+    state: Res<State<AppState>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    players: Query<(&GlobalTransform, &Controlled)>,
+    mut cameras: Query<(&mut Transform, &mut CameraRig)>,
+) {
+    let dt = time.delta_seconds();
+    for (mut transform, mut rig) in cameras.iter_mut() {
+        let player = players.iter()
+            .find(|(_, controlled)| controlled.seat == rig.seat)
+            .map(|(transform, _)| transform.translation())
+            .unwrap_or(SPAWN_POINT);
+        match rig.mode {
+            CameraMode::Orbit => rig.yaw += CAMERA_ORBIT_SPEED * dt,
+            CameraMode::FreeFly if matches!(state.get(), AppState::InGame | AppState::Editor) => {
+                let mut fly = Vec3::ZERO;
+                for (action, dir) in [
+                    (Action::Forward, Vec3::NEG_Z),
+                    (Action::Back, Vec3::Z),
+                    (Action::Left, Vec3::NEG_X),
+                    (Action::Right, Vec3::X),
+                ] {
+                    if bindings.pressed(action, &keyboard_input) {
+                        fly += rig.look_rotation() * dir;
+                    }
+                }
+                if bindings.pressed(Action::Jump, &keyboard_input) {
+                    fly += Vec3::Y;
+                }
+                if bindings.pressed(Action::FlyDown, &keyboard_input) {
+                    fly -= Vec3::Y;
+                }
+                rig.fly_position += fly.normalize_or_zero() * CAMERA_FLY_SPEED * dt;
+            }
+            _ => {}
+        }
+
+        let target = rig.target(player);
+        if rig.blend < 1.0 {
+            rig.blend = (rig.blend + dt / CAMERA_BLEND_TIME).min(1.0);
+            let t = rig.blend * rig.blend * (3.0 - 2.0 * rig.blend);
+            transform.translation = rig.blend_from.translation.lerp(target.translation, t);
+            transform.rotation = rig.blend_from.rotation.slerp(target.rotation, t);
+        } else if rig.mode == CameraMode::FreeFly {
+            *transform = target;
+        } else {
+            let t = 1.0 - (-CAMERA_FOLLOW_SMOOTHING * dt).exp();
+            transform.translation = transform.translation.lerp(target.translation, t);
+            transform.rotation = transform.rotation.slerp(target.rotation, t);
+        }
+    }
+}
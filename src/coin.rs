@@ -0,0 +1,234 @@
+//! The coin's visuals: a ridged rim plus separate heads and tails caps so each
+//! face gets its own material. Heads is the local +Y face, same as the `Up`
+//! jump caster.
+//!
+//! If `assets/models/coin.glb` exists it is used instead of the generated mesh.
+//! Its materials named `heads`, `tails` and `rim` get swapped for ours.
+
+use std::f32::consts::PI;
+
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+
+pub struct CoinPlugin;
+
+impl Plugin for CoinPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CoinAssets>()
+            .add_systems(Update, (attach_coin_model, apply_gltf_coin_materials))
+            ;
+    }
+}
+
+pub const COIN_RADIUS: f32 = 1.0;
+pub const COIN_HEIGHT: f32 = 0.2;
+const COIN_RIDGES: usize = 60;
+const COIN_RIDGE_DEPTH: f32 = 0.02;
+const COIN_FACE_SEGMENTS: usize = 64;
+
+const COIN_GLTF_FILE: &str = "models/coin.glb";
+const HEADS_TEXTURE: &str = "coin_test0.PNG";
+const TAILS_TEXTURE: &str = "coin_test1.PNG";
+
+#[derive(Resource)]
+pub struct CoinAssets {
+    pub rim_mesh: Handle<Mesh>,
+    pub heads_mesh: Handle<Mesh>,
+    pub tails_mesh: Handle<Mesh>,
+    pub rim: Handle<StandardMaterial>,
+    pub heads: Handle<StandardMaterial>,
+    pub tails: Handle<StandardMaterial>,
+    pub gltf: Option<Handle<Gltf>>,
+}
+
+impl FromWorld for CoinAssets {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>().clone();
+        let heads_texture: Handle<Image> = asset_server.load(HEADS_TEXTURE);
+        let tails_texture: Handle<Image> = asset_server.load(TAILS_TEXTURE);
+        let gltf = if std::path::Path::new("assets").join(COIN_GLTF_FILE).exists() {
+            Some(asset_server.load(COIN_GLTF_FILE))
+        } else {
+            None
+        };
+
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let rim_mesh = meshes.add(coin_rim_mesh(COIN_RADIUS, COIN_HEIGHT, COIN_RIDGES, COIN_RIDGE_DEPTH));
+        let heads_mesh = meshes.add(coin_face_mesh(COIN_RADIUS - COIN_RIDGE_DEPTH, COIN_HEIGHT / 2.0, COIN_FACE_SEGMENTS, true));
+        let tails_mesh = meshes.add(coin_face_mesh(COIN_RADIUS - COIN_RIDGE_DEPTH, -COIN_HEIGHT / 2.0, COIN_FACE_SEGMENTS, false));
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let rim = materials.add(StandardMaterial {
+            base_color: Color::rgb(0.8, 0.7, 0.6),
+            metallic: 0.8,
+            perceptual_roughness: 0.4,
+            ..default()
+        });
+        let heads = materials.add(StandardMaterial {
+            base_color_texture: Some(heads_texture),
+            metallic: 0.6,
+            perceptual_roughness: 0.5,
+            ..default()
+        });
+        let tails = materials.add(StandardMaterial {
+            base_color_texture: Some(tails_texture),
+            metallic: 0.6,
+            perceptual_roughness: 0.5,
+            ..default()
+        });
+
+        Self{rim_mesh, heads_mesh, tails_mesh, rim, heads, tails, gltf}
+    }
+}
+
+/// Marks the root of the coin's visual so skins and the glTF swap can find it.
+#[derive(Component)]
+pub struct CoinModel;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CoinPart {
+    Rim,
+    Heads,
+    Tails,
+}
+
+/// Spawns the coin visuals as a child of the player. Kept out of
+/// `player::setup` so the player can be simulated without rendering.
+pub fn spawn_coin_model(parent: &mut ChildBuilder, asset_server: &AssetServer, coin_assets: &CoinAssets) {
+    if coin_assets.gltf.is_some() {
+        parent.spawn((CoinModel, SceneBundle {
+            scene: asset_server.load(format!("{}#Scene0", COIN_GLTF_FILE)),
+            ..default()
+        }));
+        return;
+    }
+
+    parent.spawn((CoinModel, SpatialBundle::default())).with_children(|model| {
+        model.spawn((CoinPart::Rim, PbrBundle {
+            mesh: coin_assets.rim_mesh.clone(),
+            material: coin_assets.rim.clone(),
+            ..default()
+        }));
+        model.spawn((CoinPart::Heads, PbrBundle {
+            mesh: coin_assets.heads_mesh.clone(),
+            material: coin_assets.heads.clone(),
+            ..default()
+        }));
+        model.spawn((CoinPart::Tails, PbrBundle {
+            mesh: coin_assets.tails_mesh.clone(),
+            material: coin_assets.tails.clone(),
+            ..default()
+        }));
+    });
+}
+
+fn attach_coin_model(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    coin_assets: Res<CoinAssets>,
+    players: Query<Entity, Added<crate::player::Player>>,
+) {
+    for player in players.iter() {
+        commands.entity(player).with_children(|parent| {
+            spawn_coin_model(parent, &asset_server, &coin_assets);
+        });
+    }
+}
+
+/// The glTF comes with its own materials. Once its meshes show up, tag them
+/// with the matching `CoinPart` and use our materials instead.
+fn apply_gltf_coin_materials(
+    mut commands: Commands,
+    coin_assets: Res<CoinAssets>,
+    gltfs: Res<Assets<Gltf>>,
+    mut parts: Query<(Entity, &mut Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+) {
+    let Some(gltf) = coin_assets.gltf.as_ref().and_then(|handle| gltfs.get(handle)) else {
+        return;
+    };
+    for (entity, mut material) in parts.iter_mut() {
+        for (name, part, replacement) in [
+            ("heads", CoinPart::Heads, &coin_assets.heads),
+            ("tails", CoinPart::Tails, &coin_assets.tails),
+            ("rim", CoinPart::Rim, &coin_assets.rim),
+        ] {
+            if gltf.named_materials.get(name) == Some(&*material) {
+                *material = replacement.clone();
+                commands.entity(entity).insert(part);
+            }
+        }
+    }
+}
+
+/// A flat disc at height `y`. UVs map the texture across the whole face and are
+/// mirrored on the bottom so tails doesn't read backwards from below.
+pub fn coin_face_mesh(radius: f32, y: f32, segments: usize, up: bool) -> Mesh {
+    let normal = if up { [0.0, 1.0, 0.0] } else { [0.0, -1.0, 0.0] };
+    let mirror = if up { 1.0 } else { -1.0 };
+
+    let mut positions = vec![[0.0, y, 0.0]];
+    let mut normals = vec![normal];
+    let mut uvs = vec![[0.5, 0.5]];
+    for i in 0..=segments {
+        let theta = 2.0 * PI * i as f32 / segments as f32;
+        let (x, z) = (theta.cos(), theta.sin());
+        positions.push([radius * x, y, radius * z]);
+        normals.push(normal);
+        uvs.push([0.5 + 0.5 * mirror * x, 0.5 + 0.5 * z]);
+    }
+
+    let mut indices = Vec::with_capacity(segments * 3);
+    for i in 1..=segments as u32 {
+        if up {
+            indices.extend_from_slice(&[0, i + 1, i]);
+        } else {
+            indices.extend_from_slice(&[0, i, i + 1]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+/// The coin's edge, centered on the origin. Every other vertex ring is pushed in
+/// by `ridge_depth` which gives the reeded edge.
+pub fn coin_rim_mesh(radius: f32, height: f32, ridges: usize, ridge_depth: f32) -> Mesh {
+    let segments = ridges * 2;
+    let half = height / 2.0;
+
+    let mut positions = Vec::with_capacity((segments + 1) * 2);
+    let mut normals = Vec::with_capacity((segments + 1) * 2);
+    let mut uvs = Vec::with_capacity((segments + 1) * 2);
+    for i in 0..=segments {
+        let theta = 2.0 * PI * i as f32 / segments as f32;
+        let (x, z) = (theta.cos(), theta.sin());
+        let r = if i % 2 == 0 { radius } else { radius - ridge_depth };
+        let u = i as f32 / segments as f32;
+        positions.push([r * x, -half, r * z]);
+        positions.push([r * x, half, r * z]);
+        normals.push([x, 0.0, z]);
+        normals.push([x, 0.0, z]);
+        uvs.push([u, 1.0]);
+        uvs.push([u, 0.0]);
+    }
+
+    let mut indices = Vec::with_capacity(segments * 6);
+    for i in 0..segments as u32 {
+        let (b0, t0, b1, t1) = (2 * i, 2 * i + 1, 2 * i + 2, 2 * i + 3);
+        indices.extend_from_slice(&[b0, t0, b1, b1, t0, t1]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
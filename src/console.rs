@@ -0,0 +1,316 @@
+//! Drop-down developer console, opened with the key left of 1. Commands live
+//! in the `ConsoleCommands` registry, and any plugin can add its own with
+//! `app.add_console_command(...)`.
+//!
+//! Up and Down walk through the history, Tab completes command names.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::menu::text_style;
+use crate::player::{Controlled, ControlsLocked, JumpTuning, MovementTuning};
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Console>()
+            .init_resource::<ConsoleCommands>()
+            .add_console_command("help", "help: lists every command", help)
+            .add_console_command("tp", "tp x y z: moves the coin", teleport)
+            .add_console_command("spawn", "spawn cuboid sx sy sz: puts a static box in front of the coin", spawn)
+            .add_console_command("set", "set <param> <value>: changes a movement or physics parameter, `set` alone lists them", set)
+            .add_console_command("god", "god: toggles jumping from anywhere", god)
+            .add_systems(Update, (console_input, run_console_commands, console_ui).chain())
+            ;
+    }
+}
+
+const CONSOLE_KEY: KeyCode = KeyCode::Grave;
+const CONSOLE_LINES: usize = 14;
+const CONSOLE_LOG_LIMIT: usize = 200;
+const CONSOLE_BG: Color = Color::rgba(0.0, 0.0, 0.0, 0.8);
+
+/// Gets the world and the arguments after the command name. The returned text
+/// goes to the console log.
+pub type CommandFn = fn(&mut World, &[&str]) -> Result<String, String>;
+
+#[derive(Clone, Copy)]
+pub struct ConsoleCommand {
+    pub usage: &'static str,
+    pub run: CommandFn,
+}
+
+#[derive(Resource, Default, Clone)]
+pub struct ConsoleCommands(pub BTreeMap<&'static str, ConsoleCommand>);
+
+pub trait ConsoleAppExt {
+    fn add_console_command(&mut self, name: &'static str, usage: &'static str, run: CommandFn) -> &mut Self;
+}
+
+impl ConsoleAppExt for App {
+    fn add_console_command(&mut self, name: &'static str, usage: &'static str, run: CommandFn) -> &mut Self {
+        self.init_resource::<ConsoleCommands>();
+        self.world.resource_mut::<ConsoleCommands>().0.insert(name, ConsoleCommand{usage, run});
+        self
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+    pub log: Vec<String>,
+    pub history: Vec<String>,
+    /// Which history entry Up/Down is on, counted from the newest.
+    history_index: Option<usize>,
+    pending: Vec<String>,
+    locked_before: bool,
+}
+
+impl Console {
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.log.push(line.into());
+        if self.log.len() > CONSOLE_LOG_LIMIT {
+            self.log.remove(0);
+        }
+    }
+
+    /// What `ControlsLocked` goes back to once the console closes.
+    pub fn set_locked_on_close(&mut self, locked: bool) {
+        self.locked_before = locked;
+    }
+}
+
+/// For systems that read keys the console also uses.
+pub fn console_closed(console: Res<Console>) -> bool {
+    !console.open
+}
+
+#[derive(Component)]
+struct ConsoleRoot;
+
+#[derive(Component)]
+struct ConsoleText;
+
+fn console_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut console: ResMut<Console>,
+    registry: Res<ConsoleCommands>,
+    mut locked: ResMut<ControlsLocked>,
+) {
+    if keyboard_input.just_pressed(CONSOLE_KEY) {
+        console.open = !console.open;
+        // typing shouldn't move the coin
+        if console.open {
+            console.locked_before = locked.0;
+            locked.0 = true;
+        } else {
+            locked.0 = console.locked_before;
+        }
+        characters.clear();
+        return;
+    }
+    if !console.open {
+        characters.clear();
+        return;
+    }
+
+    for event in characters.iter() {
+        if !event.char.is_control() && event.char != '`' {
+            console.input.push(event.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        console.input.pop();
+    }
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        if !line.trim().is_empty() {
+            console.history.push(line.clone());
+            console.pending.push(line);
+        }
+        console.history_index = None;
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) && !console.history.is_empty() {
+        let index = console.history_index.map_or(0, |i| (i + 1).min(console.history.len() - 1));
+        console.history_index = Some(index);
+        console.input = console.history[console.history.len() - 1 - index].clone();
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        match console.history_index {
+            Some(0) | None => {
+                console.history_index = None;
+                console.input.clear();
+            }
+            Some(i) => {
+                console.history_index = Some(i - 1);
+                console.input = console.history[console.history.len() - i].clone();
+            }
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        let typed = console.input.trim_start().to_string();
+        let matches: Vec<&str> = registry.0.keys().copied().filter(|name| name.starts_with(typed.as_str())).collect();
+        match matches.as_slice() {
+            [] => {}
+            [only] => console.input = format!("{} ", only),
+            many => {
+                let line = many.join("  ");
+                console.print(line);
+            }
+        }
+    }
+}
+
+fn run_console_commands(world: &mut World) {
+    let pending = std::mem::take(&mut world.resource_mut::<Console>().pending);
+    for line in pending {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((name, args)) = words.split_first() else { continue };
+        let command = world.resource::<ConsoleCommands>().0.get(name).copied();
+        let output = match command {
+            Some(command) => (command.run)(world, args).unwrap_or_else(|err| format!("error: {}\nusage: {}", err, command.usage)),
+            None => format!("unknown command {:?}, try `help`", name),
+        };
+        let mut console = world.resource_mut::<Console>();
+        console.print(format!("> {}", line));
+        if !output.is_empty() {
+            console.print(output);
+        }
+    }
+}
+
+fn console_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    console: Res<Console>,
+    roots: Query<Entity, With<ConsoleRoot>>,
+    mut texts: Query<&mut Text, With<ConsoleText>>,
+) {
+    if !console.open {
+        for root in roots.iter() {
+            commands.entity(root).despawn_recursive();
+        }
+        return;
+    }
+    if roots.is_empty() {
+        commands.spawn((ConsoleRoot, NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(40.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::FlexEnd,
+                ..default()
+            },
+            background_color: CONSOLE_BG.into(),
+            z_index: ZIndex::Global(20),
+            ..default()
+        })).with_children(|parent| {
+            parent.spawn((ConsoleText, TextBundle::from_section("", text_style(&asset_server, 16.0))));
+        });
+    }
+    let lines: Vec<&str> = console.log.iter().flat_map(|entry| entry.lines()).collect();
+    let start = lines.len().saturating_sub(CONSOLE_LINES);
+    let label = format!("{}\n> {}_", lines[start..].join("\n"), console.input);
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
+
+pub fn parse_args<const N: usize>(args: &[&str]) -> Result<[f32; N], String> {
+    if args.len() < N {
+        return Err(format!("expected {} numbers", N));
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg.parse().map_err(|_| format!("{:?} is not a number", arg))?;
+    }
+    Ok(values)
+}
+
+fn help(world: &mut World, _args: &[&str]) -> Result<String, String> {
+    let registry = world.resource::<ConsoleCommands>();
+    Ok(registry.0.values().map(|command| command.usage).collect::<Vec<_>>().join("\n"))
+}
+
+fn teleport(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let [x, y, z] = parse_args(args)?;
+    let target = Vec3::new(x, y, z);
+    let mut players = world.query_filtered::<(&mut Position, &mut LinearVelocity, &mut Transform), With<Controlled>>();
+    for (mut position, mut velocity, mut transform) in players.iter_mut(world) {
+        position.0 = target;
+        velocity.0 = Vec3::ZERO;
+        transform.translation = target;
+    }
+    Ok(format!("moved to {}", target))
+}
+
+fn spawn(world: &mut World, args: &[&str]) -> Result<String, String> {
+    match args.first() {
+        Some(&"cuboid") => {}
+        _ => return Err("only `cuboid` can be spawned".to_string()),
+    }
+    let [sx, sy, sz] = parse_args(&args[1..])?;
+    let size = Vec3::new(sx, sy, sz);
+    let mut players = world.query_filtered::<&Position, With<Controlled>>();
+    let at = players.iter(world).next().map(|p| p.0).unwrap_or_default() + Vec3::new(0.0, 0.0, -3.0 - 0.5 * sz);
+    let mesh = world.resource_mut::<Assets<Mesh>>().add(shape::Box::new(sx, sy, sz).into());
+    let material = world.resource_mut::<Assets<StandardMaterial>>().add(Color::rgb(0.6, 0.4, 0.2).into());
+    world.spawn((PbrBundle {
+        mesh,
+        material,
+        transform: Transform::from_translation(at),
+        ..default()
+    }, RigidBody::Static, Collider::cuboid(sx, sy, sz)));
+    Ok(format!("spawned a {} cuboid at {}", size, at))
+}
+
+const SET_PARAMS: &[&str] = &[
+    "gravity", "coyote", "buffer",
+    "ground_accel", "ground_drag", "ground_max_speed",
+    "air_accel", "air_drag", "air_max_speed",
+    "magnus", "max_slope", "slope_accel",
+];
+
+fn set(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let Some(param) = args.first() else {
+        return Ok(SET_PARAMS.join(" "));
+    };
+    let [value] = parse_args(&args[1..])?;
+    match *param {
+        "gravity" => world.resource_mut::<Gravity>().0 = Vec3::NEG_Y * value,
+        "coyote" => world.resource_mut::<JumpTuning>().coyote_time = value,
+        "buffer" => world.resource_mut::<JumpTuning>().buffer_time = value,
+        _ => {
+            let mut tuning = world.resource_mut::<MovementTuning>();
+            match *param {
+                "ground_accel" => tuning.ground.accel = value,
+                "ground_drag" => tuning.ground.drag = value,
+                "ground_max_speed" => tuning.ground.max_speed = value,
+                "air_accel" => tuning.air.accel = value,
+                "air_drag" => tuning.air.drag = value,
+                "air_max_speed" => tuning.air.max_speed = value,
+                "magnus" => tuning.magnus = value,
+                // movement divides by it to scale grip
+                "max_slope" if value <= 0.0 => return Err(format!("max_slope has to be above 0, got {}", value)),
+                "max_slope" => tuning.max_slope = value.to_radians(),
+                "slope_accel" => tuning.slope_accel = value,
+                _ => return Err(format!("unknown parameter {:?}, one of: {}", param, SET_PARAMS.join(" "))),
+            }
+        }
+    }
+    Ok(format!("{} = {}", param, value))
+}
+
+fn god(world: &mut World, _args: &[&str]) -> Result<String, String> {
+    let mut tuning = world.resource_mut::<JumpTuning>();
+    tuning.jump_anywhere = !tuning.jump_anywhere;
+    Ok(format!("god mode {}", if tuning.jump_anywhere { "on" } else { "off" }))
+}
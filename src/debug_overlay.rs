@@ -0,0 +1,182 @@
+//! Debug drawing, toggled with F3. Collider outlines, contact points with
+//! their normals, the two jump casters and the coin's velocity are drawn as
+//! gizmos, next to a text panel with the coin's state.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::helpers::YRotation;
+use crate::menu::text_style;
+use crate::physics_debug::PhysicsDebug;
+use crate::player::{Controlled, GroundState, Player, PlayerJump, WallState};
+use crate::settings::{Action, KeyBindings};
+
+pub struct DebugOverlayPlugin;
+
+impl Plugin for DebugOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<DebugOverlay>()
+            .add_systems(Update, (toggle_overlay, sync_panel.run_if(resource_changed::<DebugOverlay>())).chain())
+            .add_systems(Update, (
+                draw_colliders,
+                draw_contacts,
+                draw_casters,
+                draw_player_motion,
+                update_panel,
+            ).run_if(overlay_enabled))
+            ;
+    }
+}
+
+const COLLIDER_COLOR: Color = Color::rgba(0.3, 1.0, 0.3, 0.6);
+const SENSOR_COLOR: Color = Color::rgba(0.3, 0.6, 1.0, 0.6);
+const CONTACT_COLOR: Color = Color::RED;
+const CASTER_COLOR: Color = Color::CYAN;
+const CASTER_HIT_COLOR: Color = Color::ORANGE;
+const VELOCITY_COLOR: Color = Color::YELLOW;
+const SPIN_COLOR: Color = Color::FUCHSIA;
+const NORMAL_LENGTH: f32 = 0.5;
+// velocity is drawn as how far the coin gets in this many seconds
+const VELOCITY_SCALE: f32 = 0.25;
+
+#[derive(Resource, Default, Debug)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+}
+
+#[derive(Component)]
+struct DebugPanel;
+
+pub fn overlay_enabled(overlay: Res<DebugOverlay>) -> bool {
+    overlay.enabled
+}
+
+fn toggle_overlay(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut overlay: ResMut<DebugOverlay>,
+) {
+    if bindings.just_pressed(Action::DebugOverlay, &keyboard_input) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+/// The overlay can also be turned on from elsewhere, like the physics stepper.
+fn sync_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    overlay: Res<DebugOverlay>,
+    panels: Query<Entity, With<DebugPanel>>,
+) {
+    if overlay.enabled == !panels.is_empty() {
+        return;
+    }
+    for panel in panels.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if overlay.enabled {
+        // above the pause menu, so it can be read while stepping from there
+        commands.spawn((DebugPanel, TextBundle {
+            z_index: ZIndex::Global(15),
+            ..TextBundle::from_section("", text_style(&asset_server, 16.0)).with_style(Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                top: Val::Px(10.0),
+                ..default()
+            })
+        }));
+    }
+}
+
+fn draw_colliders(
+    mut gizmos: Gizmos,
+    colliders: Query<(&Collider, &Position, &Rotation, Option<&Sensor>)>,
+) {
+    for (collider, position, rotation, sensor) in colliders.iter() {
+        let color = if sensor.is_some() { SENSOR_COLOR } else { COLLIDER_COLOR };
+        let shape = collider.get_shape();
+        if let Some(cuboid) = shape.as_cuboid() {
+            let size = 2.0 * Vec3::new(cuboid.half_extents.x, cuboid.half_extents.y, cuboid.half_extents.z);
+            gizmos.cuboid(Transform::from_translation(position.0).with_rotation(rotation.0).with_scale(size), color);
+        } else if let Some(cylinder) = shape.as_cylinder() {
+            let up = rotation.0 * Vec3::Y;
+            let top = position.0 + up * cylinder.half_height;
+            let bottom = position.0 - up * cylinder.half_height;
+            gizmos.circle(top, up, cylinder.radius, color);
+            gizmos.circle(bottom, up, cylinder.radius, color);
+            for side in [Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z] {
+                let offset = rotation.0 * side * cylinder.radius;
+                gizmos.line(top + offset, bottom + offset, color);
+            }
+        } else {
+            // anything else just gets a marker where it is
+            gizmos.sphere(position.0, rotation.0, 0.25, color);
+        }
+    }
+}
+
+fn draw_contacts(
+    mut gizmos: Gizmos,
+    mut collisions: EventReader<Collision>,
+) {
+    for Collision(contact) in collisions.iter() {
+        let point: Vec3 = contact.point1.into();
+        let normal: Vec3 = contact.normal.into();
+        gizmos.sphere(point, Quat::IDENTITY, 0.05, CONTACT_COLOR);
+        gizmos.ray(point, normal * NORMAL_LENGTH, CONTACT_COLOR);
+    }
+}
+
+fn draw_casters(
+    mut gizmos: Gizmos,
+    casters: Query<(&ShapeCaster, &ShapeHits, &GlobalTransform), With<PlayerJump>>,
+) {
+    for (caster, hits, transform) in casters.iter() {
+        let origin = transform.transform_point(caster.origin);
+        let direction = transform.affine().transform_vector3(caster.direction).normalize_or_zero();
+        gizmos.ray(origin, direction * caster.max_time_of_impact, CASTER_COLOR);
+        for hit in hits.iter() {
+            let at = origin + direction * hit.time_of_impact;
+            gizmos.sphere(at, Quat::IDENTITY, 0.08, CASTER_HIT_COLOR);
+            gizmos.ray(at, hit.normal1 * NORMAL_LENGTH, CASTER_HIT_COLOR);
+        }
+    }
+}
+
+fn draw_player_motion(
+    mut gizmos: Gizmos,
+    players: Query<(&Position, &LinearVelocity, &AngularVelocity), With<Player>>,
+) {
+    for (position, linear_velocity, angular_velocity) in players.iter() {
+        gizmos.ray(position.0, linear_velocity.0 * VELOCITY_SCALE, VELOCITY_COLOR);
+        gizmos.ray(position.0, angular_velocity.0 * VELOCITY_SCALE, SPIN_COLOR);
+    }
+}
+
+fn update_panel(
+    physics_debug: Res<PhysicsDebug>,
+    players: Query<(&Position, &LinearVelocity, &AngularVelocity, &GroundState, &WallState, &YRotation), With<Controlled>>,
+    mut panels: Query<&mut Text, With<DebugPanel>>,
+) {
+    let Some((position, linear_velocity, angular_velocity, ground, wall, y_rotation)) = players.iter().next() else { return };
+    let (axis, angle) = y_rotation.quat.to_axis_angle();
+    let label = format!(
+        "{}\nposition {:.2}\nvelocity {:.2} ({:.2} m/s)\nspin {:.2}\nground {:?} on {:?}, slope {:.1} deg\nwall {:?}\nheads {} yaw {:.1} deg around {:.1}",
+        physics_debug.describe(),
+        position.0,
+        linear_velocity.0,
+        linear_velocity.length(),
+        angular_velocity.0,
+        ground.face,
+        ground.entity,
+        ground.slope_angle.to_degrees(),
+        wall.normal,
+        y_rotation.heads,
+        angle.to_degrees(),
+        axis,
+    );
+    for mut text in panels.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
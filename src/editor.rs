@@ -0,0 +1,420 @@
+//! In-game level editor. F2 toggles it from a running game. The camera flies
+//! freely and the cursor sits on the grid point straight ahead of it.
+//!
+//! Every edit goes straight into `CurrentLevel`, which rebuilds the level, and
+//! the level before the edit is kept for undo.
+//!
+//! Controls: 1-9, 0, - and = pick a piece, left click or Enter places it, Q selects the
+//! piece nearest the cursor, Delete removes it. G cycles move/rotate/scale and
+//! I/J/K/L/U/O apply it along x, z and y. F switches between the
+//! `CUBOID_SIZE` grid and a fine one, a tenth of it. Ctrl+Z/Ctrl+Y undo and redo,
+//! Ctrl+S/Ctrl+O save and load, P moves the spawn point to the cursor and F5
+//! drops the coin at the cursor to test play.
+//!
+//! A prop's shape, material and mass and the signal a button or door is on
+//! start out at their defaults, change them in the saved file.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::AppState;
+use crate::camera::{CameraMode, CameraRig};
+use crate::console::console_closed;
+use crate::game_const::*;
+use crate::level::{load_level_file, save_level_file, CurrentLevel, LevelFile, LevelPiece, PieceKind};
+use crate::menu::{despawn_with, text_style};
+use crate::physics_debug::PhysicsDebug;
+use crate::player::{Controlled, ControlsLocked};
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<EditorState>()
+            .add_systems(OnEnter(AppState::Editor), (enter_editor, editor_setup))
+            .add_systems(OnExit(AppState::Editor), (exit_editor, despawn_with::<EditorRoot>))
+            .add_systems(Update, (
+                editor_cursor,
+                editor_place,
+                editor_transform,
+                editor_history,
+                editor_files,
+                editor_test_play,
+                editor_gizmos,
+                editor_status,
+            ).chain().run_if(in_state(AppState::Editor).and_then(console_closed)))
+            .add_systems(Update, toggle_editor)
+            ;
+    }
+}
+
+const EDITOR_GRID: f32 = CUBOID_SIZE;
+// buttons, collectibles and props are much smaller than a cuboid and need
+// finer placement than the grid the floors and walls line up on
+const EDITOR_FINE_GRID: f32 = CUBOID_SIZE / 10.0;
+// how far in front of the camera the cursor sits
+const EDITOR_REACH: f32 = 12.0;
+const EDITOR_ROTATE_STEP: f32 = 15.0;
+const EDITOR_UNDO_LIMIT: usize = 100;
+const SELECT_RANGE: f32 = CUBOID_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GizmoMode {
+    Move,
+    Rotate,
+    Scale,
+}
+
+#[derive(Resource, Debug)]
+pub struct EditorState {
+    pub kind: PieceKind,
+    pub selected: Option<usize>,
+    pub gizmo: GizmoMode,
+    /// Snapping to `EDITOR_FINE_GRID` instead of `EDITOR_GRID`.
+    pub fine_grid: bool,
+    pub cursor: Vec3,
+    undo: Vec<LevelFile>,
+    redo: Vec<LevelFile>,
+    message: String,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self{
+            kind: PieceKind::Floor,
+            selected: None,
+            gizmo: GizmoMode::Move,
+            fine_grid: false,
+            cursor: Vec3::ZERO,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            message: String::new(),
+        }
+    }
+}
+
+impl EditorState {
+    pub fn grid(&self) -> f32 {
+        if self.fine_grid { EDITOR_FINE_GRID } else { EDITOR_GRID }
+    }
+
+    /// Call before changing `level`, so the change can be undone.
+    fn record(&mut self, level: &LevelFile) {
+        self.undo.push(level.clone());
+        if self.undo.len() > EDITOR_UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+}
+
+#[derive(Component)]
+struct EditorRoot;
+
+#[derive(Component)]
+struct EditorStatus;
+
+pub fn snap(position: Vec3, grid: f32) -> Vec3 {
+    (position / grid).round() * grid
+}
+
+fn toggle_editor(
+    keyboard_input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F2) {
+        return;
+    }
+    match state.get() {
+        AppState::InGame => next_state.set(AppState::Editor),
+        AppState::Editor => next_state.set(AppState::InGame),
+        _ => {}
+    }
+}
+
+fn enter_editor(
+    mut physics_loop: ResMut<PhysicsLoop>,
+    mut locked: ResMut<ControlsLocked>,
+    mut current: ResMut<CurrentLevel>,
+    mut cameras: Query<(&Transform, &mut CameraRig)>,
+) {
+    physics_loop.pause();
+    locked.0 = true;
+    if current.0.is_none() {
+        current.0 = Some(LevelFile::default());
+    }
+    for (transform, mut rig) in cameras.iter_mut() {
+        rig.set_mode(CameraMode::FreeFly, *transform);
+    }
+}
+
+fn exit_editor(
+    mut physics_loop: ResMut<PhysicsLoop>,
+    physics_debug: Res<PhysicsDebug>,
+    mut locked: ResMut<ControlsLocked>,
+    mut cameras: Query<(&Transform, &mut CameraRig)>,
+) {
+    if !physics_debug.frozen {
+        physics_loop.resume();
+    }
+    locked.0 = false;
+    for (transform, mut rig) in cameras.iter_mut() {
+        rig.set_mode(CameraMode::Follow, *transform);
+    }
+}
+
+fn editor_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((EditorRoot, NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            flex_direction: FlexDirection::Column,
+            ..default()
+        },
+        background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+        ..default()
+    })).with_children(|parent| {
+        parent.spawn((EditorStatus, TextBundle::from_section("", text_style(&asset_server, 18.0))));
+    });
+}
+
+fn editor_cursor(
+    mut editor: ResMut<EditorState>,
+    cameras: Query<&GlobalTransform, With<CameraRig>>,
+) {
+    let Some(camera) = cameras.iter().next() else { return };
+    editor.cursor = snap(camera.translation() + camera.forward() * EDITOR_REACH, editor.grid());
+}
+
+fn editor_place(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut editor: ResMut<EditorState>,
+    mut current: ResMut<CurrentLevel>,
+) {
+    let number_keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9, KeyCode::Key0, KeyCode::Minus, KeyCode::Equals];
+    for (key, kind) in number_keys.into_iter().zip(PieceKind::ALL) {
+        if keyboard_input.just_pressed(key) {
+            editor.kind = kind;
+        }
+    }
+    // only `as_mut` when something changes, that's what rebuilds the level
+    if let Some(level) = current.0.as_ref().filter(|_| keyboard_input.just_pressed(KeyCode::Q)) {
+        let cursor = editor.cursor;
+        editor.selected = level.pieces.iter()
+            .enumerate()
+            .map(|(i, piece)| (i, piece.position.distance(cursor)))
+            .filter(|(_, distance)| *distance < SELECT_RANGE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i);
+    }
+
+    let place = mouse_input.just_pressed(MouseButton::Left) || keyboard_input.just_pressed(KeyCode::Return);
+    let delete = keyboard_input.any_just_pressed([KeyCode::Delete, KeyCode::Back]) && editor.selected.is_some();
+    let move_spawn = keyboard_input.just_pressed(KeyCode::P);
+    if !(place || delete || move_spawn) {
+        return;
+    }
+    let Some(level) = current.0.as_mut() else { return };
+    editor.record(level);
+    if place {
+        level.pieces.push(LevelPiece::new(editor.kind, editor.cursor));
+        editor.selected = Some(level.pieces.len() - 1);
+    }
+    if delete {
+        if let Some(index) = editor.selected.take().filter(|i| *i < level.pieces.len()) {
+            level.pieces.remove(index);
+        }
+    }
+    if move_spawn {
+        level.spawn = editor.cursor;
+    }
+}
+
+fn editor_transform(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut editor: ResMut<EditorState>,
+    mut current: ResMut<CurrentLevel>,
+) {
+    if keyboard_input.just_pressed(KeyCode::G) {
+        editor.gizmo = match editor.gizmo {
+            GizmoMode::Move => GizmoMode::Rotate,
+            GizmoMode::Rotate => GizmoMode::Scale,
+            GizmoMode::Scale => GizmoMode::Move,
+        };
+    }
+    if keyboard_input.just_pressed(KeyCode::F) {
+        editor.fine_grid = !editor.fine_grid;
+    }
+    // Ctrl+O is load, not a nudge down
+    if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let mut axis = Vec3::ZERO;
+    for (key, dir) in [
+        (KeyCode::L, Vec3::X),
+        (KeyCode::J, Vec3::NEG_X),
+        (KeyCode::K, Vec3::Z),
+        (KeyCode::I, Vec3::NEG_Z),
+        (KeyCode::U, Vec3::Y),
+        (KeyCode::O, Vec3::NEG_Y),
+    ] {
+        if keyboard_input.just_pressed(key) {
+            axis += dir;
+        }
+    }
+    if axis == Vec3::ZERO {
+        return;
+    }
+    let Some(index) = editor.selected else { return };
+    // only touch the level (and so rebuild it) when there is something to change
+    let Some(level) = current.0.as_mut() else { return };
+    if index >= level.pieces.len() {
+        return;
+    }
+    editor.record(level);
+    let grid = editor.grid();
+    let piece = &mut level.pieces[index];
+    match editor.gizmo {
+        GizmoMode::Move => piece.position += axis * grid,
+        GizmoMode::Rotate => piece.rotation += axis * EDITOR_ROTATE_STEP,
+        GizmoMode::Scale => piece.size = (piece.size + axis * grid).max(Vec3::splat(0.1)),
+    }
+}
+
+fn editor_history(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut editor: ResMut<EditorState>,
+    mut current: ResMut<CurrentLevel>,
+) {
+    if !keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+        || !keyboard_input.any_just_pressed([KeyCode::Z, KeyCode::Y])
+    {
+        return;
+    }
+    let Some(level) = current.0.as_mut() else { return };
+    if keyboard_input.just_pressed(KeyCode::Z) {
+        if let Some(previous) = editor.undo.pop() {
+            editor.redo.push(std::mem::replace(level, previous));
+            editor.selected = None;
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Y) {
+        if let Some(next) = editor.redo.pop() {
+            editor.undo.push(std::mem::replace(level, next));
+            editor.selected = None;
+        }
+    }
+}
+
+fn editor_files(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut editor: ResMut<EditorState>,
+    mut current: ResMut<CurrentLevel>,
+) {
+    if !keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::S) {
+        let Some(level) = current.0.as_ref() else { return };
+        editor.message = match save_level_file(level) {
+            Ok(()) => format!("saved {}", level.name),
+            Err(err) => format!("could not save {}: {:?}", level.name, err),
+        };
+    }
+    if keyboard_input.just_pressed(KeyCode::O) {
+        let name = current.0.as_ref().map(|level| level.name.clone()).unwrap_or_default();
+        match load_level_file(&name) {
+            Ok(loaded) => {
+                if let Some(level) = current.0.as_ref() {
+                    editor.record(level);
+                }
+                current.0 = Some(loaded);
+                editor.selected = None;
+                editor.message = format!("loaded {}", name);
+            }
+            Err(err) => editor.message = format!("could not load {}: {:?}", name, err),
+        }
+    }
+}
+
+fn editor_test_play(
+    keyboard_input: Res<Input<KeyCode>>,
+    editor: Res<EditorState>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut players: Query<(&mut Position, &mut Rotation, &mut LinearVelocity, &mut AngularVelocity), With<Controlled>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F5) {
+        return;
+    }
+    for (mut position, mut rotation, mut linear_velocity, mut angular_velocity) in players.iter_mut() {
+        position.0 = editor.cursor + Vec3::Y;
+        *rotation = Rotation::default();
+        linear_velocity.0 = Vec3::ZERO;
+        angular_velocity.0 = Vec3::ZERO;
+    }
+    next_state.set(AppState::InGame);
+}
+
+fn editor_gizmos(
+    mut gizmos: Gizmos,
+    editor: Res<EditorState>,
+    current: Res<CurrentLevel>,
+) {
+    gizmos.cuboid(Transform::from_translation(editor.cursor).with_scale(Vec3::splat(EDITOR_FINE_GRID * 0.5)), Color::WHITE);
+    let Some(level) = current.0.as_ref() else { return };
+    gizmos.sphere(level.spawn, Quat::IDENTITY, 0.5, Color::GREEN);
+
+    for (i, piece) in level.pieces.iter().enumerate() {
+        let selected = editor.selected == Some(i);
+        let color = if selected { Color::YELLOW } else { Color::rgba(1.0, 1.0, 1.0, 0.2) };
+        gizmos.cuboid(piece.transform().with_scale(piece.size), color);
+        if !selected {
+            continue;
+        }
+        let rotation = piece.rotation_quat();
+        let reach = piece.size.max_element() * 0.5 + 1.0;
+        for (axis, axis_color) in [(Vec3::X, Color::RED), (Vec3::Y, Color::GREEN), (Vec3::Z, Color::BLUE)] {
+            let dir = rotation * axis;
+            match editor.gizmo {
+                GizmoMode::Move => gizmos.ray(piece.position, dir * reach, axis_color),
+                GizmoMode::Rotate => {
+                    gizmos.circle(piece.position, dir, reach, axis_color);
+                }
+                GizmoMode::Scale => {
+                    gizmos.ray(piece.position, dir * reach, axis_color);
+                    gizmos.cuboid(Transform::from_translation(piece.position + dir * reach).with_rotation(rotation).with_scale(Vec3::splat(0.3)), axis_color);
+                }
+            }
+        }
+    }
+}
+
+fn editor_status(
+    editor: Res<EditorState>,
+    current: Res<CurrentLevel>,
+    mut labels: Query<&mut Text, With<EditorStatus>>,
+) {
+    let level = current.0.as_ref();
+    let selected = editor.selected
+        .and_then(|i| level.and_then(|level| level.pieces.get(i)))
+        .map(|piece| format!("{} at {:.1} rot {:.0} size {:.1}", piece.kind.name(), piece.position, piece.rotation, piece.size))
+        .unwrap_or_else(|| "nothing".to_string());
+    let label = format!(
+        "Editing {} ({} pieces)\nPlacing: {}\nGizmo: {:?}, grid {}\nSelected: {}\nCursor: {:.1}\n{}",
+        level.map(|level| level.name.as_str()).unwrap_or("-"),
+        level.map(|level| level.pieces.len()).unwrap_or(0),
+        editor.kind.name(),
+        editor.gizmo,
+        editor.grid(),
+        selected,
+        editor.cursor,
+        editor.message,
+    );
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
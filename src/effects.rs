@@ -0,0 +1,238 @@
+//! Visual feedback, all simulated on the CPU. Particles are tiny unlit
+//! spheres that move, shrink and despawn on their own.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::coin::{CoinModel, COIN_RADIUS};
+use crate::game_const::*;
+use crate::player::{GroundState, JumpStrength, Player, PlayerFlipped, PlayerJumped, PlayerLanded};
+
+pub struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<EffectAssets>()
+            .add_systems(Update, (
+                landing_dust,
+                jump_dust,
+                wall_sparks,
+                flip_trail,
+                jump_charge_pulse,
+                update_particles,
+            ))
+            ;
+    }
+}
+
+const DUST_PER_SPEED: f32 = 2.0;
+const MAX_DUST: usize = 30;
+// how fast the rim has to slide along a wall before it sparks
+const SPARK_MIN_SPEED: f32 = 3.0;
+const FLIP_TRAIL_SPEED: f32 = 12.0;
+const PULSE_FREQUENCY: f32 = 6.0;
+const PULSE_SIZE: f32 = 0.06;
+
+#[derive(Resource)]
+pub struct EffectAssets {
+    pub particle_mesh: Handle<Mesh>,
+    pub dust: Handle<StandardMaterial>,
+    pub spark: Handle<StandardMaterial>,
+    pub trail: Handle<StandardMaterial>,
+}
+
+impl FromWorld for EffectAssets {
+    fn from_world(world: &mut World) -> Self {
+        let particle_mesh = world.resource_mut::<Assets<Mesh>>().add(Mesh::from(shape::UVSphere {
+            radius: 0.5,
+            sectors: 6,
+            stacks: 4,
+        }));
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut unlit = |color: Color| materials.add(StandardMaterial {
+            base_color: color,
+            unlit: true,
+            ..default()
+        });
+        Self{
+            particle_mesh,
+            dust: unlit(Color::rgb(0.6, 0.55, 0.5)),
+            spark: unlit(Color::rgb(1.0, 0.8, 0.3)),
+            trail: unlit(Color::rgb(0.9, 0.85, 0.7)),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Particle {
+    pub velocity: Vec3,
+    pub gravity: f32,
+    pub drag: f32,
+    pub age: f32,
+    pub lifetime: f32,
+    pub size: f32,
+}
+
+/// Small deterministic scatter so effects don't need a random number generator.
+fn scatter(i: usize, seed: f32) -> Vec3 {
+    let a = (i as f32 * 2.399 + seed).sin();
+    let b = (i as f32 * 1.618 + seed * 3.1).cos();
+    let c = (i as f32 * 0.733 + seed * 7.7).sin();
+    Vec3::new(a, b, c)
+}
+
+pub fn spawn_particle(
+    commands: &mut Commands,
+    assets: &EffectAssets,
+    material: &Handle<StandardMaterial>,
+    position: Vec3,
+    particle: Particle,
+) {
+    commands.spawn((PbrBundle {
+        mesh: assets.particle_mesh.clone(),
+        material: material.clone(),
+        transform: Transform::from_translation(position).with_scale(Vec3::splat(particle.size)),
+        ..default()
+    }, particle));
+}
+
+fn dust_puff(commands: &mut Commands, assets: &EffectAssets, position: Vec3, strength: f32, seed: f32) {
+    let count = ((strength * DUST_PER_SPEED) as usize).min(MAX_DUST);
+    for i in 0..count {
+        let dir = scatter(i, seed);
+        let outward = Vec3::new(dir.x, 0.0, dir.z).normalize_or_zero();
+        spawn_particle(commands, assets, &assets.dust, position + outward * COIN_RADIUS, Particle {
+            velocity: outward * (1.0 + 0.2 * strength) + Vec3::Y * (0.5 + 0.5 * dir.y.abs()),
+            gravity: -1.0,
+            drag: 3.0,
+            age: 0.0,
+            lifetime: 0.6 + 0.2 * dir.y.abs(),
+            size: 0.15,
+        });
+    }
+}
+
+fn landing_dust(
+    mut commands: Commands,
+    assets: Res<EffectAssets>,
+    time: Res<Time>,
+    mut landed: EventReader<PlayerLanded>,
+    players: Query<&GlobalTransform, With<Player>>,
+) {
+    for event in landed.iter() {
+        let Ok(transform) = players.get(event.player) else { continue };
+        dust_puff(&mut commands, &assets, transform.translation(), event.impact_speed, time.elapsed_seconds());
+    }
+}
+
+fn jump_dust(
+    mut commands: Commands,
+    assets: Res<EffectAssets>,
+    time: Res<Time>,
+    mut jumped: EventReader<PlayerJumped>,
+    players: Query<&GlobalTransform, With<Player>>,
+) {
+    for event in jumped.iter() {
+        let Ok(transform) = players.get(event.player) else { continue };
+        dust_puff(&mut commands, &assets, transform.translation(), event.strength * 0.5, time.elapsed_seconds());
+    }
+}
+
+fn wall_sparks(
+    mut commands: Commands,
+    assets: Res<EffectAssets>,
+    time: Res<Time>,
+    mut collisions: EventReader<Collision>,
+    players: Query<(&GlobalTransform, &LinearVelocity), With<Player>>,
+) {
+    for Collision(contact) in collisions.iter() {
+        for player in [contact.entity1, contact.entity2] {
+            let Ok((transform, velocity)) = players.get(player) else { continue };
+            let normal: Vec3 = contact.normal.into();
+            // only walls, and only while the rim is the part touching them
+            let is_wall = normal.y.abs() < 0.3;
+            let rim_contact = transform.up().dot(normal).abs() < 0.5;
+            let sliding = velocity.0.reject_from(normal).length();
+            if !is_wall || !rim_contact || sliding < SPARK_MIN_SPEED {
+                continue;
+            }
+            let side = if player == contact.entity1 { normal } else { -normal };
+            let position = transform.translation() + side * COIN_RADIUS;
+            for i in 0..3 {
+                let dir = scatter(i, time.elapsed_seconds() * 13.0);
+                spawn_particle(&mut commands, &assets, &assets.spark, position, Particle {
+                    velocity: -side * 2.0 + dir * 1.5 + Vec3::Y,
+                    gravity: -9.0,
+                    drag: 0.5,
+                    age: 0.0,
+                    lifetime: 0.3,
+                    size: 0.05,
+                });
+            }
+        }
+    }
+}
+
+fn flip_trail(
+    mut commands: Commands,
+    assets: Res<EffectAssets>,
+    players: Query<(&GlobalTransform, &AngularVelocity, &GroundState), With<Player>>,
+    mut flipped: EventReader<PlayerFlipped>,
+) {
+    let flipping = flipped.iter().count() > 0;
+    for (transform, angular_velocity, ground) in players.iter() {
+        if ground.grounded() || (angular_velocity.length() < FLIP_TRAIL_SPEED && !flipping) {
+            continue;
+        }
+        // leave a dot on both ends of the spinning rim
+        let axis = transform.forward();
+        for side in [-1.0, 1.0] {
+            spawn_particle(&mut commands, &assets, &assets.trail, transform.translation() + side * axis * COIN_RADIUS, Particle {
+                velocity: Vec3::ZERO,
+                gravity: 0.0,
+                drag: 0.0,
+                age: 0.0,
+                lifetime: 0.25,
+                size: 0.08,
+            });
+        }
+    }
+}
+
+fn jump_charge_pulse(
+    time: Res<Time>,
+    players: Query<(&JumpStrength, &Children), With<Player>>,
+    mut models: Query<&mut Transform, With<CoinModel>>,
+) {
+    for (jump_strength, children) in players.iter() {
+        let charge = jump_strength.0 / MAX_JUMP_TIME_LENGTH;
+        let pulse = 1.0 + PULSE_SIZE * charge * (time.elapsed_seconds() * PULSE_FREQUENCY * (1.0 + charge)).sin().abs();
+        for child in children.iter() {
+            if let Ok(mut transform) = models.get_mut(*child) {
+                transform.scale = Vec3::splat(pulse);
+            }
+        }
+    }
+}
+
+fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform)>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut particle, mut transform) in particles.iter_mut() {
+        particle.age += dt;
+        if particle.age >= particle.lifetime {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let drag = particle.drag;
+        particle.velocity.y += particle.gravity * dt;
+        particle.velocity *= (1.0 - drag * dt).max(0.0);
+        transform.translation += particle.velocity * dt;
+        let life_left = 1.0 - particle.age / particle.lifetime;
+        transform.scale = Vec3::splat(particle.size * life_left);
+    }
+}
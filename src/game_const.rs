@@ -6,10 +6,57 @@ pub const ROT_SPEED: f32 = 10.0;
 pub const BASE_JUMP_STRNGTH: f32 = 6.0;
 pub const BASE_FLIP_STRNGTH: f32 = 20.0;
 pub const MAX_JUMP_TIME_LENGTH: f32 = 1.0;
+// bevy_xpbd's default gravity, the coin falls PLAYER_GRAVITY_SCALE times faster
+pub const GRAVITY: f32 = 9.81;
+pub const PLAYER_GRAVITY_SCALE: f32 = 2.0;
+// a face counts as down when its direction is at most 60 degrees off straight down
+pub const GROUND_FACE_MIN_DOWN: f32 = 0.5;
 
+// accelerations in m/s², drag per second, max speed is what input can push up to
+pub const GROUND_ACCEL: f32 = 60.0;
+pub const GROUND_DRAG: f32 = 8.0;
+pub const GROUND_MAX_SPEED: f32 = 8.0;
+pub const AIR_ACCEL: f32 = 20.0;
+pub const AIR_DRAG: f32 = 0.2;
+pub const AIR_MAX_SPEED: f32 = 8.0;
+// steeper than this (radians) and the coin can't hold on, it slides
+pub const MAX_WALKABLE_SLOPE: f32 = 0.7;
+pub const SLOPE_ACCEL: f32 = 12.0;
+// sideways push per unit of spin times speed while airborne
+pub const MAGNUS_COEFFICIENT: f32 = 0.02;
+
+pub const COYOTE_TIME: f32 = 0.12;
+pub const JUMP_BUFFER_TIME: f32 = 0.15;
+
+pub const WALL_CAST_DISTANCE: f32 = 0.15;
+pub const WALL_JUMP_PUSH: f32 = 8.0;
+pub const WALL_JUMP_UP: f32 = 9.0;
+pub const WALL_JUMP_COOLDOWN: f32 = 0.35;
+pub const WALL_SLIDE_MAX_SPEED: f32 = 2.0;
+
+// above this speed the player gets swept casts and extra substeps
+pub const CCD_SPEED: f32 = 15.0;
+pub const BASE_SUBSTEPS: u32 = 12;
+pub const MAX_SUBSTEPS: u32 = 48;
+// xpbd's default fixed timestep, one physics tick
+pub const PHYSICS_TICK: f32 = 1.0 / 60.0;
+
+
+// the testmap is laid out in units of this, levels default to it too
+pub const CUBOID_SIZE: f32 = 10.0;
+pub const CUBOID_DEPTH: f32 = 0.2;
 
 pub const SPAWN_POINT: Vec3 = Vec3::new(0.0,5.0,0.0);
 pub const CAMERA_RELATIVE: Vec3 = Vec3::new(0.0,1.5,5.0);
 pub const CAMERA_LOOK: Vec3 = Vec3::new(0.0,1.5,0.0);
+pub const CAMERA_BLEND_TIME: f32 = 0.6;
+pub const CAMERA_FOLLOW_SMOOTHING: f32 = 8.0;
+pub const CAMERA_TOP_DOWN_HEIGHT: f32 = 18.0;
+pub const CAMERA_ORBIT_DISTANCE: f32 = 9.0;
+pub const CAMERA_ORBIT_SPEED: f32 = 0.4;
+pub const CAMERA_FLY_SPEED: f32 = 12.0;
+// radians per second at full right stick tilt
+pub const CAMERA_STICK_SPEED: f32 = 2.5;
+pub const GAMEPAD_DEADZONE: f32 = 0.2;
 pub const SENS_X: f32 = 0.01;
 pub const SENS_Y: f32 = 0.01;
\ No newline at end of file
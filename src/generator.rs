@@ -0,0 +1,244 @@
+//! Seeded parkour courses: a chain of platforms, gaps, sticky pads and ramps
+//! that ends in a goal. Every gap and rise stays inside what a full speed,
+//! fully charged jump clears, with some slack. Courses are plain level files,
+//! so they get saved to `assets/levels/` and can be edited like any other.
+//!
+//! The daily course is seeded with the day number, so it is the same for
+//! everyone on the same day.
+
+use std::f32::consts::PI;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::coin::COIN_RADIUS;
+use crate::console::ConsoleAppExt;
+use crate::game_const::*;
+use crate::level::{save_level_file, LevelFile, LevelPiece, LoadLevel, PieceKind, LEVEL_VERSION};
+use crate::player::JumpEnvelope;
+
+pub struct GeneratorPlugin;
+
+impl Plugin for GeneratorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_console_command("generate", "generate [seed]: builds, saves and loads a parkour course, random without a seed", generate)
+            .add_console_command("daily", "daily: loads today's course", daily)
+            ;
+    }
+}
+
+// high above the testmap, falling off drops the coin back into it
+const COURSE_ORIGIN: Vec3 = Vec3::new(0.0, 30.0, -40.0);
+const COURSE_SEGMENTS: usize = 16;
+// share of the jump envelope a course may ask for
+const REACH_SLACK: f32 = 0.75;
+// the coin's center has to end up this far onto the next platform
+const LANDING_MARGIN: f32 = COIN_RADIUS;
+// narrower than the coin and it would just roll over it
+const MIN_GAP: f32 = 2.5 * COIN_RADIUS;
+const MAX_DROP: f32 = 3.0;
+const PLATFORM_THICKNESS: f32 = 0.5;
+const MIN_PLATFORM: f32 = 3.0;
+const MAX_PLATFORM: f32 = 7.0;
+const MIN_RAMP_LENGTH: f32 = 6.0;
+const MAX_RAMP_LENGTH: f32 = 10.0;
+const RAMP_CHANCE: f32 = 0.2;
+const STICKY_CHANCE: f32 = 0.25;
+// per segment turn and overall heading limit, so the course can't curl back into itself
+const MAX_TURN: f32 = 0.5;
+const MAX_HEADING: f32 = 0.35 * PI;
+const CHECKPOINT_EVERY: usize = 5;
+const LIGHT_EVERY: usize = 3;
+// level names, the seed or the date goes after
+pub const COURSE_PREFIX: &str = "course_";
+pub const DAILY_PREFIX: &str = "daily_";
+
+/// SplitMix64. Kept in house so a seed builds the same course on every build
+/// and platform.
+pub struct CourseRng(pub u64);
+
+impl CourseRng {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+}
+
+/// Direction a yaw faces, -Z at 0 like the rest of bevy.
+fn heading(yaw: f32) -> Vec3 {
+    Vec3::new(-yaw.sin(), 0.0, -yaw.cos())
+}
+
+/// The top face of a platform, what the next piece gets placed against.
+#[derive(Clone, Copy, Debug)]
+struct Footing {
+    top: Vec3,
+    yaw: f32,
+    /// Half the width across and half the length along the yaw.
+    half: Vec2,
+}
+
+impl Footing {
+    /// How far the top face reaches from its center towards `direction`.
+    fn reach(&self, direction: Vec3) -> f32 {
+        let forward = heading(self.yaw);
+        let right = Vec3::new(self.yaw.cos(), 0.0, -self.yaw.sin());
+        self.half.x * direction.dot(right).abs() + self.half.y * direction.dot(forward).abs()
+    }
+
+    fn piece(&self, kind: PieceKind, thickness: f32) -> LevelPiece {
+        LevelPiece{
+            rotation: Vec3::new(0.0, self.yaw.to_degrees(), 0.0),
+            size: Vec3::new(2.0 * self.half.x, thickness, 2.0 * self.half.y),
+            ..LevelPiece::new(kind, self.top - Vec3::Y * (0.5 * thickness))
+        }
+    }
+}
+
+pub fn generate_course(seed: u64, envelope: &JumpEnvelope) -> LevelFile {
+    let mut rng = CourseRng(seed);
+    let mut pieces = Vec::new();
+    let mut yaw = 0.0;
+
+    let mut last = Footing{top: COURSE_ORIGIN, yaw, half: Vec2::splat(0.5 * CUBOID_SIZE)};
+    pieces.push(last.piece(PieceKind::Floor, PLATFORM_THICKNESS));
+    pieces.push(LevelPiece::new(PieceKind::Light, COURSE_ORIGIN + Vec3::Y * 8.0));
+
+    for segment in 1..=COURSE_SEGMENTS {
+        yaw = (yaw + rng.range(-MAX_TURN, MAX_TURN)).clamp(-MAX_HEADING, MAX_HEADING);
+        let direction = heading(yaw);
+        let last_segment = segment == COURSE_SEGMENTS;
+        // the goal gets a roomier platform
+        let min_size = if last_segment { MAX_PLATFORM } else { MIN_PLATFORM };
+        let half = 0.5 * Vec2::new(rng.range(min_size, MAX_PLATFORM), rng.range(min_size, MAX_PLATFORM));
+        let edge = last.top + direction * last.reach(direction);
+
+        let top = if rng.chance(RAMP_CHANCE) {
+            // walked up, no jump, so the only limit is the slope
+            let length = rng.range(MIN_RAMP_LENGTH, MAX_RAMP_LENGTH);
+            let angle = rng.range(0.2, 0.8 * MAX_WALKABLE_SLOPE);
+            let rotation = Quat::from_euler(EulerRot::YXZ, yaw, angle, 0.0);
+            let along = rotation * Vec3::NEG_Z;
+            pieces.push(LevelPiece{
+                rotation: Vec3::new(angle.to_degrees(), yaw.to_degrees(), 0.0),
+                size: Vec3::new(2.0 * half.x, PLATFORM_THICKNESS, length),
+                ..LevelPiece::new(PieceKind::Floor, edge + along * (0.5 * length) - rotation * Vec3::Y * (0.5 * PLATFORM_THICKNESS))
+            });
+            edge + along * length + direction * half.y
+        } else {
+            // pick the rise first, then a gap that can still be cleared with it
+            let rise = rng.range(-MAX_DROP, REACH_SLACK * envelope.max_height());
+            let reach = envelope.max_distance(rise).unwrap_or(0.0) * REACH_SLACK - LANDING_MARGIN;
+            let gap = rng.range(MIN_GAP, reach.max(MIN_GAP));
+            edge + direction * (gap + half.y) + Vec3::Y * rise
+        };
+
+        let next = Footing{top, yaw, half};
+        pieces.push(next.piece(PieceKind::Floor, PLATFORM_THICKNESS));
+        if !last_segment && rng.chance(STICKY_CHANCE) {
+            let pad = Footing{top: top + Vec3::Y * 0.1, yaw, half: 0.6 * half};
+            pieces.push(pad.piece(PieceKind::StickyField, 0.1));
+        }
+        if last_segment {
+            pieces.push(LevelPiece::new(PieceKind::Goal, top + Vec3::Y * (0.5 * PieceKind::Goal.default_size().y)));
+        } else if segment % CHECKPOINT_EVERY == 0 {
+            pieces.push(LevelPiece::new(PieceKind::Checkpoint, top + Vec3::Y * (0.5 * PieceKind::Checkpoint.default_size().y)));
+        }
+        if segment % LIGHT_EVERY == 0 {
+            pieces.push(LevelPiece::new(PieceKind::Light, top + Vec3::Y * 8.0));
+        }
+        last = next;
+    }
+
+    LevelFile{
+        version: LEVEL_VERSION,
+        name: format!("{}{}", COURSE_PREFIX, seed),
+        spawn: COURSE_ORIGIN + Vec3::Y * 3.0,
+        pieces,
+        next: None,
+    }
+}
+
+/// A seed from the clock, for when nobody picked one.
+pub fn random_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos() as u64).unwrap_or_default()
+}
+
+/// Today's course, named after the date.
+pub fn daily_course() -> LevelFile {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() / 86400)
+        .unwrap_or_default();
+    let (year, month, day) = civil_date(days as i64);
+    let mut level = generate_course(days, &JumpEnvelope::default());
+    level.name = format!("{}{}-{:02}-{:02}", DAILY_PREFIX, year, month, day);
+    level
+}
+
+/// Days since 1970-01-01 to a calendar date, Howard Hinnant's `civil_from_days`.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn play_course(world: &mut World, level: LevelFile) -> Result<String, String> {
+    save_level_file(&level).map_err(|err| format!("could not save {}: {:?}", level.name, err))?;
+    let message = format!("built {} with {} pieces", level.name, level.pieces.len());
+    world.send_event(LoadLevel(level.name));
+    Ok(message)
+}
+
+fn generate(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let seed = match args.first() {
+        Some(arg) => arg.parse::<u64>().map_err(|_| format!("{:?} is not a seed", arg))?,
+        None => random_seed(),
+    };
+    play_course(world, generate_course(seed, &JumpEnvelope::default()))
+}
+
+fn daily(world: &mut World, _args: &[&str]) -> Result<String, String> {
+    play_course(world, daily_course())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reachability::check_level;
+
+    const CHECKED_SEEDS: u64 = 50;
+
+    #[test]
+    fn generated_courses_are_reachable() {
+        let envelope = JumpEnvelope::default();
+        for seed in 0..CHECKED_SEEDS {
+            let report = check_level(&generate_course(seed, &envelope), &envelope);
+            assert!(report.is_clean(), "seed {}: {}", seed, report.describe());
+        }
+    }
+}
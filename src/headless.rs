@@ -0,0 +1,241 @@
+//! Runs the physics and the player without a window or GPU, one fixed tick
+//! per `step`, with keyboard input read from a script.
+//!
+//! ```ignore
+//! let mut sim = HeadlessAppBuilder::new()
+//!     .with_testmap()
+//!     .with_script(InputScript::new().hold(&[KeyCode::Space], 60).hold(&[KeyCode::S], 60))
+//!     .build();
+//! sim.run_script();
+//! assert!(sim.player_position().y > 3.1);
+//! ```
+//!
+//! Scenarios live next to the code they exercise and run with `cargo test`.
+
+use std::time::Duration;
+
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::game_const::PHYSICS_TICK;
+use crate::level::{CurrentLevel, LevelFile, LevelPiece};
+use crate::player::{spawn_coin, Controlled, GroundState, PlayerPlugin};
+use crate::props::Prop;
+use crate::settings::{KeyBindings, SecondPlayerBindings};
+
+// testmap landmarks for placing coins in scenarios
+pub const TESTMAP_FLOOR_TOP: f32 = 1.0;
+// the first parkour block, 0.2 thick
+pub const TESTMAP_PARKOUR_STEP: Vec3 = Vec3::new(20.0, 3.0, 0.0);
+pub const TESTMAP_PARKOUR_STEP_TOP: f32 = 3.1;
+// the east wall, 0.2 thick
+pub const TESTMAP_EAST_WALL_X: f32 = 25.0;
+// the ramp rises towards -z
+pub const TESTMAP_RAMP_CENTER: Vec3 = Vec3::new(-15.0, 2.7, -60.0);
+
+/// Keys held on each tick. Keys are pressed on the first tick they show up
+/// and released on the first tick they're gone, so `just_pressed` works.
+#[derive(Resource, Default, Clone)]
+pub struct InputScript {
+    pub ticks: Vec<Vec<KeyCode>>,
+    pub current: usize,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn hold(mut self, keys: &[KeyCode], ticks: usize) -> Self {
+        for _ in 0..ticks {
+            self.ticks.push(keys.to_vec());
+        }
+        self
+    }
+
+    pub fn wait(self, ticks: usize) -> Self {
+        self.hold(&[], ticks)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ticks.len()
+    }
+}
+
+fn apply_input_script(
+    mut script: ResMut<InputScript>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+) {
+    // nothing else clears the input without the InputPlugin
+    keyboard_input.clear();
+    let previous = script.current.checked_sub(1).and_then(|i| script.ticks.get(i)).cloned().unwrap_or_default();
+    let current = script.ticks.get(script.current).cloned().unwrap_or_default();
+    for key in previous.iter().filter(|key| !current.contains(key)) {
+        keyboard_input.release(*key);
+    }
+    for key in current.iter().filter(|key| !previous.contains(key)) {
+        keyboard_input.press(*key);
+    }
+    script.current += 1;
+}
+
+pub struct HeadlessAppBuilder {
+    app: App,
+    script: InputScript,
+}
+
+impl HeadlessAppBuilder {
+    pub fn new() -> Self {
+        let mut app = App::new();
+        app
+            .add_plugins(MinimalPlugins)
+            .add_plugins((TransformPlugin, HierarchyPlugin, AssetPlugin::default()))
+            // the levels still create meshes and materials, they're just never drawn
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .add_asset::<Image>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(PHYSICS_TICK)))
+            .add_plugins(PhysicsPlugins::default())
+            .insert_resource(PhysicsTimestep::FixedOnce(PHYSICS_TICK))
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<SecondPlayerBindings>()
+            .init_resource::<Gamepads>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .add_systems(PreUpdate, apply_input_script)
+            .add_plugins(PlayerPlugin)
+            ;
+        Self{app, script: InputScript::default()}
+    }
+
+    pub fn with_testmap(self) -> Self {
+        self.with_plugin(crate::testmap::TestMapPlugin)
+            .with_plugin(crate::level_logic::LevelLogicPlugin)
+    }
+
+    pub fn with_plugin(mut self, plugin: impl Plugin) -> Self {
+        self.app.add_plugins(plugin);
+        self
+    }
+
+    pub fn with_script(mut self, script: InputScript) -> Self {
+        self.script = script;
+        self
+    }
+
+    pub fn build(mut self) -> HeadlessApp {
+        // the first update runs the startup systems along with everything
+        // else, the script starts on the one after
+        self.app.insert_resource(InputScript::default());
+        self.app.update();
+        self.app.insert_resource(self.script);
+        HeadlessApp{app: self.app}
+    }
+}
+
+pub struct HeadlessApp {
+    pub app: App,
+}
+
+impl HeadlessApp {
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    pub fn run_ticks(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    /// Steps until the input script has run out.
+    pub fn run_script(&mut self) {
+        let remaining = {
+            let script = self.app.world.resource::<InputScript>();
+            script.len().saturating_sub(script.current)
+        };
+        self.run_ticks(remaining);
+    }
+
+    /// Steps once with exactly these keys held, after whatever the script had.
+    pub fn step_with(&mut self, keys: &[KeyCode]) {
+        {
+            let mut script = self.app.world.resource_mut::<InputScript>();
+            let current = script.current;
+            // pad over ticks that ran past the end of the script
+            script.ticks.resize(current, Vec::new());
+            script.ticks.push(keys.to_vec());
+        }
+        self.step();
+    }
+
+    pub fn world(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+
+    /// Adds another coin, without handing it control.
+    pub fn spawn_coin(&mut self, position: Vec3) -> Entity {
+        let mut queue = CommandQueue::default();
+        let coin = spawn_coin(&mut Commands::new(&mut queue, &self.app.world), position);
+        queue.apply(&mut self.app.world);
+        coin
+    }
+
+    pub fn position_of(&mut self, entity: Entity) -> Vec3 {
+        self.app.world.get::<Position>(entity).map(|p| p.0).unwrap_or_default()
+    }
+
+    /// The coin the script is driving.
+    pub fn player(&mut self) -> Entity {
+        self.app.world.query_filtered::<Entity, With<Controlled>>().single(&self.app.world)
+    }
+
+    /// Puts the player somewhere else, at rest and flat.
+    pub fn place_player(&mut self, position: Vec3) {
+        let player = self.player();
+        let mut entity = self.app.world.entity_mut(player);
+        entity.insert((
+            Position(position),
+            Rotation::default(),
+            LinearVelocity(Vec3::ZERO),
+            AngularVelocity(Vec3::ZERO),
+        ));
+        if let Some(mut transform) = entity.get_mut::<Transform>() {
+            *transform = Transform::from_translation(position);
+        }
+    }
+
+    pub fn set_player_velocity(&mut self, velocity: Vec3) {
+        let player = self.player();
+        self.app.world.entity_mut(player).insert(LinearVelocity(velocity));
+    }
+
+    pub fn player_position(&mut self) -> Vec3 {
+        let player = self.player();
+        self.position_of(player)
+    }
+
+    pub fn player_ground(&mut self) -> GroundState {
+        let player = self.player();
+        self.app.world.get::<GroundState>(player).copied().unwrap_or_default()
+    }
+
+    /// Builds `pieces` as the current level, on top of whatever else is loaded.
+    pub fn load_pieces(&mut self, pieces: Vec<LevelPiece>) {
+        self.app.world.resource_mut::<CurrentLevel>().0 = Some(LevelFile{pieces, ..default()});
+        self.step();
+    }
+
+    pub fn prop_position(&mut self) -> Option<Vec3> {
+        let world = &mut self.app.world;
+        world.query_filtered::<&Position, With<Prop>>().iter(world).next().map(|position| position.0)
+    }
+
+    pub fn player_velocity(&mut self) -> Vec3 {
+        let player = self.player();
+        self.app.world.get::<LinearVelocity>(player).map(|v| v.0).unwrap_or_default()
+    }
+}
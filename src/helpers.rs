@@ -6,13 +6,13 @@ pub struct HelperPlugin;
 impl Plugin for HelperPlugin {
     fn build(&self, app: &mut App) {
         app
-            .init_resource::<YRotation>()
             .add_systems(Update, y_rot_update)
             ;
     }
 }
 
-#[derive(Resource, Debug)]
+/// Which way a coin faces around the vertical axis, kept on every coin.
+#[derive(Component, Debug)]
 pub struct YRotation{
     pub heads: bool,
     pub quat: Quat, 
@@ -30,10 +30,14 @@ impl Default for YRotation {
 
 
 fn y_rot_update(
-    query_player_transform: Query<&Transform, With<crate::player::Player>>,
-    mut y_rotation: ResMut<YRotation>,
+    mut players: Query<(&Transform, &mut YRotation), With<crate::player::Player>>,
 ){
-    let player_transform = *query_player_transform.get_single().unwrap();
+    for (player_transform, mut y_rotation) in players.iter_mut() {
+        update_y_rotation(player_transform, &mut y_rotation);
+    }
+}
+
+fn update_y_rotation(player_transform: &Transform, y_rotation: &mut YRotation) {
     let player_forward = player_transform.forward();
     let y_rot = Vec2::new(0.0,-1.0).angle_between(Vec2::new(player_forward.x, player_forward.z));
     let quat = Quat::from_axis_angle(Vec3::new(0.0,1.0,0.0), -y_rot);
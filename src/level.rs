@@ -0,0 +1,491 @@
+//! Levels stored as RON files in `assets/levels/`. A level is a spawn point
+//! and a flat list of pieces. Every spawned piece is tagged with `LevelEntity`
+//! so the whole level can be torn down and rebuilt whenever `CurrentLevel`
+//! changes, which is what the editor relies on.
+
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::coin::{COIN_HEIGHT, COIN_RADIUS};
+use crate::console::ConsoleAppExt;
+use crate::level_logic::{Door, PressurePlate, SignalId};
+use crate::game_const::*;
+use crate::player::{sensor, spawn_coin, ControlsLocked, Player};
+use crate::progress::Progress;
+use crate::props::{spawn_prop, PropSpec};
+use crate::testmap::Sticky;
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CurrentLevel>()
+            .init_resource::<LevelClock>()
+            .init_resource::<Progress>()
+            .add_event::<LoadLevel>()
+            .add_event::<LevelCompleted>()
+            .add_console_command("load", "load level <name>: loads assets/levels/<name>.ron", load_command)
+            .add_systems(Update, (
+                load_requested_levels,
+                rebuild_level.run_if(resource_changed::<CurrentLevel>()),
+                tick_level_clock,
+                checkpoints_and_goals,
+            ).chain())
+            ;
+    }
+}
+
+// 2 added props, buttons and doors, 3 added collectibles
+pub const LEVEL_VERSION: u32 = 3;
+// signals from level files are offset by this, the testmap's plates and
+// doors use the low ids
+pub const LEVEL_SIGNAL_BASE: SignalId = 1000;
+const DOOR_SPEED: f32 = 8.0;
+pub const LEVEL_DIR: &str = "assets/levels";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PieceKind {
+    Floor,
+    Wall,
+    Cuboid,
+    StickyField,
+    Light,
+    Checkpoint,
+    Goal,
+    /// Another coin to hand control to, on top of the one at the spawn.
+    Coin,
+    /// Something loose to push around, see `LevelPiece::prop`.
+    Prop,
+    /// A pressure plate, see `LevelPiece::wiring`.
+    Button,
+    /// Slides up out of the way while its signal is on.
+    Door,
+    /// Picked up on touch, found ones are kept in the save profile.
+    Collectible,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 12] = [
+        PieceKind::Floor,
+        PieceKind::Wall,
+        PieceKind::Cuboid,
+        PieceKind::StickyField,
+        PieceKind::Light,
+        PieceKind::Checkpoint,
+        PieceKind::Goal,
+        PieceKind::Coin,
+        PieceKind::Prop,
+        PieceKind::Button,
+        PieceKind::Door,
+        PieceKind::Collectible,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PieceKind::Floor => "floor",
+            PieceKind::Wall => "wall",
+            PieceKind::Cuboid => "cuboid",
+            PieceKind::StickyField => "sticky field",
+            PieceKind::Light => "light",
+            PieceKind::Checkpoint => "checkpoint",
+            PieceKind::Goal => "goal",
+            PieceKind::Coin => "coin",
+            PieceKind::Prop => "prop",
+            PieceKind::Button => "button",
+            PieceKind::Door => "door",
+            PieceKind::Collectible => "collectible",
+        }
+    }
+
+    pub fn default_size(self) -> Vec3 {
+        match self {
+            PieceKind::Floor => Vec3::new(CUBOID_SIZE, 0.2, CUBOID_SIZE),
+            PieceKind::Wall => Vec3::new(CUBOID_SIZE, 0.6 * CUBOID_SIZE, 0.2),
+            PieceKind::Cuboid => Vec3::splat(0.2 * CUBOID_SIZE),
+            PieceKind::StickyField => Vec3::new(0.6 * CUBOID_SIZE, 0.1, 0.6 * CUBOID_SIZE),
+            PieceKind::Light => Vec3::splat(0.5),
+            PieceKind::Checkpoint | PieceKind::Goal => Vec3::splat(0.4 * CUBOID_SIZE),
+            PieceKind::Coin => Vec3::new(2.0 * COIN_RADIUS, COIN_HEIGHT, 2.0 * COIN_RADIUS),
+            PieceKind::Prop => Vec3::splat(1.0),
+            PieceKind::Button => Vec3::new(2.0, 0.2, 2.0),
+            PieceKind::Door => Vec3::new(0.4 * CUBOID_SIZE, 0.4 * CUBOID_SIZE, 0.2),
+            PieceKind::Collectible => Vec3::splat(0.6),
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            PieceKind::Floor => Color::rgb(0.2, 0.2, 0.2),
+            PieceKind::Wall => Color::rgb(0.0, 0.0, 1.0),
+            PieceKind::Cuboid => Color::rgb(0.0, 0.0, 0.5),
+            PieceKind::StickyField => Color::rgb(0.0, 0.5, 0.0),
+            PieceKind::Light => Color::rgb(1.0, 0.9, 0.6),
+            PieceKind::Checkpoint => Color::rgba(0.2, 0.6, 1.0, 0.3),
+            PieceKind::Goal => Color::rgba(1.0, 0.8, 0.1, 0.4),
+            PieceKind::Coin => Color::rgb(0.9, 0.7, 0.2),
+            PieceKind::Prop => Color::rgb(0.55, 0.35, 0.15),
+            PieceKind::Button => Color::rgb(0.8, 0.1, 0.1),
+            PieceKind::Door => Color::rgb(0.5, 0.3, 0.1),
+            PieceKind::Collectible => Color::rgba(1.0, 0.9, 0.2, 0.8),
+        }
+    }
+
+    /// Whether the coin collides with it, as opposed to passing through.
+    pub fn is_solid(self) -> bool {
+        matches!(self, PieceKind::Floor | PieceKind::Wall | PieceKind::Cuboid | PieceKind::StickyField | PieceKind::Door)
+    }
+}
+
+/// How a button or a door is hooked up. Doors only use the signal.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct Wiring {
+    pub signal: SignalId,
+    /// What has to be on a button for it to go down, in kg. 0 takes anything.
+    #[serde(default)]
+    pub min_mass: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LevelPiece {
+    pub kind: PieceKind,
+    pub position: Vec3,
+    /// Euler angles in degrees, applied y first, then x, then z.
+    pub rotation: Vec3,
+    /// Full lengths along each local axis.
+    pub size: Vec3,
+    /// Shape, material and mass of a `Prop`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prop: Option<PropSpec>,
+    /// For a `Button` or a `Door`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wiring: Option<Wiring>,
+}
+
+impl LevelPiece {
+    pub fn new(kind: PieceKind, position: Vec3) -> Self {
+        Self{
+            kind,
+            position,
+            rotation: Vec3::ZERO,
+            size: kind.default_size(),
+            prop: (kind == PieceKind::Prop).then(PropSpec::default),
+            wiring: matches!(kind, PieceKind::Button | PieceKind::Door).then(Wiring::default),
+        }
+    }
+
+    pub fn rotation_quat(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::YXZ,
+            self.rotation.y.to_radians(),
+            self.rotation.x.to_radians(),
+            self.rotation.z.to_radians(),
+        )
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position).with_rotation(self.rotation_quat())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct LevelFile {
+    pub version: u32,
+    pub name: String,
+    pub spawn: Vec3,
+    pub pieces: Vec<LevelPiece>,
+    /// The level finishing this one unlocks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+impl Default for LevelFile {
+    fn default() -> Self {
+        Self{
+            version: LEVEL_VERSION,
+            name: "untitled".to_string(),
+            spawn: SPAWN_POINT,
+            pieces: Vec::new(),
+            next: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Parse(String),
+    TooNew(u32),
+}
+
+impl From<std::io::Error> for LevelError {
+    fn from(err: std::io::Error) -> Self {
+        LevelError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for LevelError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        LevelError::Parse(err.to_string())
+    }
+}
+
+impl From<ron::Error> for LevelError {
+    fn from(err: ron::Error) -> Self {
+        LevelError::Parse(err.to_string())
+    }
+}
+
+pub fn level_path(name: &str) -> PathBuf {
+    PathBuf::from(LEVEL_DIR).join(format!("{}.ron", name))
+}
+
+pub fn parse_level(text: &str) -> Result<LevelFile, LevelError> {
+    let level: LevelFile = ron::from_str(text)?;
+    if level.version > LEVEL_VERSION {
+        return Err(LevelError::TooNew(level.version));
+    }
+    Ok(level)
+}
+
+pub fn load_level_file(name: &str) -> Result<LevelFile, LevelError> {
+    let text = fs::read_to_string(level_path(name))?;
+    parse_level(&text)
+}
+
+pub fn save_level_file(level: &LevelFile) -> Result<(), LevelError> {
+    fs::create_dir_all(LEVEL_DIR)?;
+    let text = ron::ser::to_string_pretty(level, ron::ser::PrettyConfig::default())?;
+    fs::write(level_path(&level.name), text)?;
+    Ok(())
+}
+
+/// The level built from a file, if any. The hand written testmap is not one.
+#[derive(Resource, Default, Debug)]
+pub struct CurrentLevel(pub Option<LevelFile>);
+
+/// Seconds since the level was built, not counting time with the controls
+/// locked, like a race countdown.
+#[derive(Resource, Default, Debug)]
+pub struct LevelClock(pub f32);
+
+/// Where a coin goes back to on reset, each coin has its own.
+#[derive(Component, Default, Debug)]
+pub struct LastCheckpoint(pub Option<Vec3>);
+
+impl LastCheckpoint {
+    pub fn respawn_point(&self, level: &CurrentLevel) -> Vec3 {
+        self.0
+            .or_else(|| level.0.as_ref().map(|level| level.spawn))
+            .unwrap_or(SPAWN_POINT)
+    }
+}
+
+/// Loads `assets/levels/<name>.ron` and puts the coin on its spawn point.
+#[derive(Event)]
+pub struct LoadLevel(pub String);
+
+#[derive(Event)]
+pub struct LevelCompleted {
+    pub level: String,
+    /// The coin that reached the goal.
+    pub player: Entity,
+    /// `LevelClock` when it got there.
+    pub time: f32,
+}
+
+/// Every entity spawned from `CurrentLevel`. The index is the piece it came from.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LevelEntity(pub usize);
+
+#[derive(Component)]
+pub struct Checkpoint;
+
+#[derive(Component)]
+pub struct Goal;
+
+/// Found once per save profile. The id is the level name and the piece index.
+#[derive(Component, Debug)]
+pub struct Collectible(pub String);
+
+pub fn collectible_id(level: &str, index: usize) -> String {
+    format!("{}/{}", level, index)
+}
+
+pub fn spawn_piece(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    index: usize,
+    piece: &LevelPiece,
+) -> Entity {
+    if piece.kind == PieceKind::Coin {
+        let coin = spawn_coin(commands, piece.position);
+        commands.entity(coin).insert(LevelEntity(index));
+        return coin;
+    }
+    if piece.kind == PieceKind::Prop {
+        let spec = piece.prop.unwrap_or_default();
+        let prop = spawn_prop(commands, meshes, materials, spec, piece.transform(), piece.size);
+        commands.entity(prop).insert(LevelEntity(index));
+        return prop;
+    }
+    if piece.kind == PieceKind::Light {
+        return commands.spawn((LevelEntity(index), PointLightBundle {
+            point_light: PointLight {
+                intensity: 1500.0,
+                shadows_enabled: true,
+                ..default()
+            },
+            transform: piece.transform(),
+            ..default()
+        })).id();
+    }
+
+    let color = piece.kind.color();
+    let material = materials.add(StandardMaterial {
+        base_color: color,
+        alpha_mode: if color.a() < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+        ..default()
+    });
+    let mut entity = commands.spawn((LevelEntity(index), PbrBundle {
+        mesh: meshes.add(shape::Box::new(piece.size.x, piece.size.y, piece.size.z).into()),
+        material,
+        transform: piece.transform(),
+        ..default()
+    }));
+    // without these xpbd starts the body at the GlobalTransform, which isn't
+    // propagated yet and would put every piece at the origin
+    entity.insert((
+        RigidBody::Static,
+        Position(piece.position),
+        Rotation(piece.rotation_quat()),
+        Collider::cuboid(piece.size.x, piece.size.y, piece.size.z),
+    ));
+    match piece.kind {
+        PieceKind::StickyField => { entity.insert(Sticky); }
+        PieceKind::Checkpoint => { entity.insert((Checkpoint, sensor())); }
+        PieceKind::Goal => { entity.insert((Goal, sensor())); }
+        // the id needs the level name, `rebuild_level` adds the Collectible
+        PieceKind::Collectible => { entity.insert(sensor()); }
+        PieceKind::Button => {
+            let wiring = piece.wiring.unwrap_or_default();
+            let plate = PressurePlate::new(LEVEL_SIGNAL_BASE + wiring.signal).with_min_mass(wiring.min_mass);
+            entity.insert((plate, sensor()));
+        }
+        PieceKind::Door => {
+            let wiring = piece.wiring.unwrap_or_default();
+            entity.insert((
+                Door{signal: LEVEL_SIGNAL_BASE + wiring.signal, closed: piece.position, open_offset: Vec3::Y * piece.size.y, speed: DOOR_SPEED},
+                RigidBody::Kinematic,
+            ));
+        }
+        _ => {}
+    }
+    entity.id()
+}
+
+fn load_requested_levels(
+    mut requests: EventReader<LoadLevel>,
+    mut current: ResMut<CurrentLevel>,
+    // coins from the old level go away with it
+    mut players: Query<(&mut Position, &mut LinearVelocity, &mut Transform, &mut LastCheckpoint), (With<Player>, Without<LevelEntity>)>,
+) {
+    let Some(LoadLevel(name)) = requests.iter().last() else { return };
+    let level = match load_level_file(name) {
+        Ok(level) => level,
+        Err(err) => {
+            error!("could not load level {:?}: {:?}", name, err);
+            return;
+        }
+    };
+    for (mut position, mut velocity, mut transform, mut checkpoint) in players.iter_mut() {
+        checkpoint.0 = None;
+        position.0 = level.spawn;
+        velocity.0 = Vec3::ZERO;
+        transform.translation = level.spawn;
+    }
+    current.0 = Some(level);
+}
+
+fn load_command(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let name = match args {
+        ["level", name] | [name] => name.to_string(),
+        _ => return Err("expected a level name".to_string()),
+    };
+    if !level_path(&name).exists() {
+        return Err(format!("there is no {:?}", level_path(&name)));
+    }
+    world.send_event(LoadLevel(name.clone()));
+    Ok(format!("loading {}", name))
+}
+
+fn rebuild_level(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    current: Res<CurrentLevel>,
+    mut clock: ResMut<LevelClock>,
+    spawned: Query<Entity, With<LevelEntity>>,
+) {
+    clock.0 = 0.0;
+    for entity in spawned.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some(level) = current.0.as_ref() else { return };
+    for (index, piece) in level.pieces.iter().enumerate() {
+        let entity = spawn_piece(&mut commands, &mut meshes, &mut materials, index, piece);
+        if piece.kind == PieceKind::Collectible {
+            commands.entity(entity).insert(Collectible(collectible_id(&level.name, index)));
+        }
+    }
+}
+
+fn tick_level_clock(
+    time: Res<Time>,
+    locked: Res<ControlsLocked>,
+    mut clock: ResMut<LevelClock>,
+) {
+    if !locked.0 {
+        clock.0 += time.delta_seconds();
+    }
+}
+
+fn checkpoints_and_goals(
+    mut commands: Commands,
+    mut started: EventReader<CollisionStarted>,
+    current: Res<CurrentLevel>,
+    clock: Res<LevelClock>,
+    mut progress: ResMut<Progress>,
+    mut completed: EventWriter<LevelCompleted>,
+    mut players: Query<&mut LastCheckpoint, With<Player>>,
+    checkpoints: Query<&GlobalTransform, With<Checkpoint>>,
+    goals: Query<(), With<Goal>>,
+    collectibles: Query<&Collectible>,
+) {
+    for CollisionStarted(e1, e2) in started.iter() {
+        for (player, other) in [(*e1, *e2), (*e2, *e1)] {
+            let Ok(mut checkpoint) = players.get_mut(player) else { continue };
+            if let Ok(transform) = checkpoints.get(other) {
+                checkpoint.0 = Some(transform.translation());
+            }
+            if let Ok(Collectible(id)) = collectibles.get(other) {
+                progress.collectibles_found.insert(id.clone());
+                commands.entity(other).despawn_recursive();
+            }
+            if goals.contains(other) {
+                let name = current.0.as_ref().map(|level| level.name.clone()).unwrap_or_default();
+                if let Some(next) = current.0.as_ref().and_then(|level| level.next.clone()) {
+                    progress.unlocked_levels.insert(next);
+                }
+                progress.record_completion(&name, clock.0);
+                completed.send(LevelCompleted { level: name, player, time: clock.0 });
+            }
+        }
+    }
+}
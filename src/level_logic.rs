@@ -0,0 +1,313 @@
+//! Level logic: pressure plates emit signals, logic nodes combine them and
+//! doors, moving platforms and lights react to them.
+//!
+//! Everything is wired by `SignalId`. A plate writes its signal every frame,
+//! logic nodes read their inputs and write their output, and targets only
+//! ever read. Signals that nobody writes are simply off.
+
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_xpbd_3d::{prelude::*, PhysicsSchedule, PhysicsStepSet};
+
+use crate::player::{movement, Player};
+use crate::props::Prop;
+
+pub struct LevelLogicPlugin;
+
+impl Plugin for LevelLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Signals>()
+            .add_systems(Update, (
+                pressure_plates,
+                logic_nodes,
+                signal_lights,
+            ).chain())
+            // velocities set per physics tick, or above 60 fps the doors overshoot
+            .add_systems(PhysicsSchedule, (doors, moving_platforms).chain().before(movement).before(PhysicsStepSet::BroadPhase))
+            ;
+    }
+}
+
+pub type SignalId = u32;
+
+#[derive(Resource, Default, Debug)]
+pub struct Signals(pub HashMap<SignalId, bool>);
+
+impl Signals {
+    pub fn get(&self, id: SignalId) -> bool {
+        *self.0.get(&id).unwrap_or(&false)
+    }
+
+    pub fn set(&mut self, id: SignalId, on: bool) {
+        self.0.insert(id, on);
+    }
+}
+
+/// Which side of the coin has to face up for a plate to trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlateFace {
+    Any,
+    Heads,
+    Tails,
+}
+
+#[derive(Component)]
+pub struct PressurePlate {
+    pub signal: SignalId,
+    pub face: PlateFace,
+    pub min_impact_speed: f32,
+    /// Everything on the plate together has to weigh this much, in kg,
+    /// counting whatever is stacked on top of what touches it.
+    pub min_mass: f32,
+    // coins and props that landed hard enough and are still touching the plate
+    pub occupants: Vec<Entity>,
+}
+
+impl PressurePlate {
+    pub fn new(signal: SignalId) -> Self {
+        Self{signal, face: PlateFace::Any, min_impact_speed: 0.0, min_mass: 0.0, occupants: Vec::new()}
+    }
+
+    pub fn with_face(mut self, face: PlateFace) -> Self {
+        self.face = face;
+        self
+    }
+
+    pub fn with_min_impact_speed(mut self, speed: f32) -> Self {
+        self.min_impact_speed = speed;
+        self
+    }
+
+    pub fn with_min_mass(mut self, mass: f32) -> Self {
+        self.min_mass = mass;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LogicKind {
+    And,
+    Or,
+    /// Stays on for `duration` seconds after any input turns on.
+    Timer { duration: f32 },
+}
+
+#[derive(Component)]
+pub struct LogicNode {
+    pub kind: LogicKind,
+    pub inputs: Vec<SignalId>,
+    pub output: SignalId,
+    pub remaining: f32,
+}
+
+impl LogicNode {
+    pub fn new(kind: LogicKind, inputs: Vec<SignalId>, output: SignalId) -> Self {
+        Self{kind, inputs, output, remaining: 0.0}
+    }
+
+    /// The output for these inputs, `dt` seconds after the last frame.
+    fn evaluate(&self, signals: &Signals, dt: f32) -> bool {
+        let any = self.inputs.iter().any(|id| signals.get(*id));
+        match self.kind {
+            LogicKind::And => !self.inputs.is_empty() && self.inputs.iter().all(|id| signals.get(*id)),
+            LogicKind::Or => any,
+            LogicKind::Timer { .. } => any || self.remaining - dt > 0.0,
+        }
+    }
+}
+
+/// Slides between its closed position and `closed + open_offset` while the
+/// signal is on. Needs a kinematic body so the coin gets pushed properly.
+#[derive(Component)]
+pub struct Door {
+    pub signal: SignalId,
+    pub closed: Vec3,
+    pub open_offset: Vec3,
+    pub speed: f32,
+}
+
+/// Travels back and forth between `from` and `to` while the signal is on.
+#[derive(Component)]
+pub struct MovingPlatform {
+    pub signal: SignalId,
+    pub from: Vec3,
+    pub to: Vec3,
+    pub speed: f32,
+    pub forward: bool,
+}
+
+#[derive(Component)]
+pub struct SignalLight {
+    pub signal: SignalId,
+    pub on_intensity: f32,
+    pub off_intensity: f32,
+}
+
+
+fn pressure_plates(
+    mut started: EventReader<CollisionStarted>,
+    mut ended: EventReader<CollisionEnded>,
+    mut plates: Query<&mut PressurePlate>,
+    bodies: Query<(&GlobalTransform, &LinearVelocity, &Mass, &CollidingEntities, Has<Player>), Or<(With<Player>, With<Prop>)>>,
+    mut signals: ResMut<Signals>,
+) {
+    for CollisionStarted(e1, e2) in started.iter() {
+        for (plate_entity, other) in [(*e1, *e2), (*e2, *e1)] {
+            let Ok(mut plate) = plates.get_mut(plate_entity) else { continue };
+            let Ok((_, velocity, ..)) = bodies.get(other) else { continue };
+            if velocity.length() >= plate.min_impact_speed && !plate.occupants.contains(&other) {
+                plate.occupants.push(other);
+            }
+        }
+    }
+    for CollisionEnded(e1, e2) in ended.iter() {
+        for (plate_entity, other) in [(*e1, *e2), (*e2, *e1)] {
+            if let Ok(mut plate) = plates.get_mut(plate_entity) {
+                plate.occupants.retain(|e| *e != other);
+            }
+        }
+    }
+
+    for plate in plates.iter() {
+        // the sensor only sees the bottom of a stack, walk up through whatever
+        // rests on the occupants
+        let mut stack = plate.occupants.clone();
+        let mut next = 0;
+        while let Some(&below) = stack.get(next) {
+            next += 1;
+            let Ok((below_transform, _, _, colliding, _)) = bodies.get(below) else { continue };
+            for other in colliding.iter() {
+                let Ok((transform, ..)) = bodies.get(*other) else { continue };
+                if transform.translation().y > below_transform.translation().y && !stack.contains(other) {
+                    stack.push(*other);
+                }
+            }
+        }
+
+        // the face only matters for coins someone plays, props always count
+        let counted: Vec<f32> = stack.iter()
+            .filter_map(|e| {
+                let (transform, _, mass, _, is_player) = bodies.get(*e).ok()?;
+                let heads_up = transform.up().y > 0.0;
+                let counts = !is_player || match plate.face {
+                    PlateFace::Any => true,
+                    PlateFace::Heads => heads_up,
+                    PlateFace::Tails => !heads_up,
+                };
+                counts.then_some(mass.0)
+            })
+            .collect();
+        let pressed = !counted.is_empty() && counted.iter().sum::<f32>() >= plate.min_mass;
+        signals.set(plate.signal, pressed);
+    }
+}
+
+fn logic_nodes(
+    time: Res<Time>,
+    mut nodes: Query<&mut LogicNode>,
+    mut signals: ResMut<Signals>,
+) {
+    let dt = time.delta_seconds();
+    // nodes can feed each other, so go over them until nothing changes
+    // instead of lagging a frame per link. A loop that never settles stops
+    // once every node had a pass.
+    for _ in 0..=nodes.iter().len() {
+        let mut changed = false;
+        for node in nodes.iter() {
+            let out = node.evaluate(&signals, dt);
+            if out != signals.get(node.output) {
+                signals.set(node.output, out);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    // timers only count down once the inputs settled
+    for mut node in nodes.iter_mut() {
+        if let LogicKind::Timer { duration } = node.kind {
+            let any = node.inputs.iter().any(|id| signals.get(*id));
+            node.remaining = if any { duration } else { (node.remaining - dt).max(0.0) };
+        }
+    }
+}
+
+fn doors(
+    delta_time: Res<DeltaTime>,
+    signals: Res<Signals>,
+    mut doors: Query<(&Door, &Position, &mut LinearVelocity)>,
+) {
+    let dt = delta_time.0;
+    if dt <= 0.0 {
+        return;
+    }
+    for (door, position, mut velocity) in doors.iter_mut() {
+        let target = if signals.get(door.signal) { door.closed + door.open_offset } else { door.closed };
+        let current: Vec3 = position.0.into();
+        let to_target = target - current;
+        // velocity instead of teleporting so the coin gets carried, slowing
+        // down on the last tick so the door stops on its mark
+        let speed = door.speed.min(to_target.length() / dt);
+        velocity.0 = (to_target.normalize_or_zero() * speed).into();
+    }
+}
+
+fn moving_platforms(
+    delta_time: Res<DeltaTime>,
+    signals: Res<Signals>,
+    mut platforms: Query<(&mut MovingPlatform, &Position, &mut LinearVelocity)>,
+) {
+    let dt = delta_time.0;
+    if dt <= 0.0 {
+        return;
+    }
+    for (mut platform, position, mut velocity) in platforms.iter_mut() {
+        if !signals.get(platform.signal) {
+            velocity.0 = Vec3::ZERO.into();
+            continue;
+        }
+        let current: Vec3 = position.0.into();
+        let target = if platform.forward { platform.to } else { platform.from };
+        let to_target = target - current;
+        if to_target.length() < 0.05 {
+            platform.forward = !platform.forward;
+        }
+        // velocity instead of teleporting so the coin rides along with it,
+        // without stepping past the end in one tick
+        let speed = platform.speed.min(to_target.length() / dt);
+        velocity.0 = (to_target.normalize_or_zero() * speed).into();
+    }
+}
+
+fn signal_lights(
+    signals: Res<Signals>,
+    mut lights: Query<(&SignalLight, &mut PointLight)>,
+) {
+    for (signal_light, mut light) in lights.iter_mut() {
+        light.intensity = if signals.get(signal_light.signal) { signal_light.on_intensity } else { signal_light.off_intensity };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::*;
+
+    #[test]
+    fn chained_nodes_settle_in_one_frame() {
+        let mut sim = HeadlessAppBuilder::new().with_plugin(LevelLogicPlugin).build();
+        // spawned downstream first, so it's also the first one looked at
+        sim.world().spawn(LogicNode::new(LogicKind::And, vec![2, 3], 4));
+        sim.world().spawn(LogicNode::new(LogicKind::Or, vec![1], 2));
+        sim.world().spawn(LogicNode::new(LogicKind::Or, vec![1], 3));
+        sim.step();
+        assert!(!sim.world().resource::<Signals>().get(4), "the chain is on without input");
+
+        sim.world().resource_mut::<Signals>().set(1, true);
+        sim.step();
+        assert!(sim.world().resource::<Signals>().get(4), "the end of the chain lags behind its input");
+    }
+}
@@ -3,8 +3,32 @@ use bevy::prelude::*;
 use bevy_xpbd_3d::{math::*, prelude::*, PhysicsSchedule, PhysicsStepSet};
 
 mod player;
+mod camera;
+mod coin;
+mod skins;
+mod progress;
+mod menu;
+mod settings;
+mod save;
+mod settings_menu;
+mod audio;
+mod effects;
+mod headless;
 mod helpers;
+mod debug_overlay;
+mod physics_debug;
+mod console;
 mod game_const;
+mod testmap;
+mod level_logic;
+mod level;
+mod props;
+mod generator;
+mod reachability;
+mod race;
+mod net;
+mod net_server;
+mod editor;
 
 use crate::game_const::*;
 
@@ -14,12 +38,24 @@ pub enum AppState {
     Menu,
     InGame,
     Paused,
+    Editor,
 }
 
 const INSPECT: bool = true;
 
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--server") {
+        if let Err(err) = net_server::run_server() {
+            eprintln!("{}", err);
+        }
+        std::process::exit(1);
+    }
+    if let Some(name) = std::env::args().skip_while(|arg| arg != "--check-level").nth(1) {
+        let clean = reachability::run_check(&name);
+        std::process::exit(if clean { 0 } else { 1 });
+    }
+
     App::new()
         // ----------  Initial Setup ----------
         .add_plugins(DefaultPlugins.set(WindowPlugin{
@@ -32,18 +68,38 @@ fn main() {
             ..default()
         }).set(ImagePlugin::default_nearest()))
         .add_state::<AppState>()
+        .init_resource::<progress::Progress>()
         .add_systems(Startup, startup_setup)
-        .add_systems(Update, debugging_ctrls)
+        .add_systems(Update, debugging_ctrls.after(player::read_coin_input))
 
         .add_plugins(PhysicsPlugins::default())
         //.add_startup_system(setup_physics)
+        .add_plugins(coin::CoinPlugin)
+        .add_plugins(skins::SkinPlugin)
+        .add_plugins(settings::SettingsPlugin)
+        .add_plugins(save::SavePlugin)
         .add_plugins(player::PlayerPlugin)
+        .add_plugins(camera::CameraRigPlugin)
+        .add_plugins(testmap::TestMapPlugin)
+        .add_plugins(level_logic::LevelLogicPlugin)
+        .add_plugins(level::LevelPlugin)
+        .add_plugins(props::PropsPlugin)
+        .add_plugins(generator::GeneratorPlugin)
+        .add_plugins(race::RacePlugin)
+        .add_plugins(net::NetClientPlugin)
+        .add_plugins(audio::SoundPlugin)
+        .add_plugins(effects::EffectsPlugin)
 
         // ----------  Always Running ----------
         .add_plugins(helpers::HelperPlugin)
+        .add_plugins(debug_overlay::DebugOverlayPlugin)
+        .add_plugins(physics_debug::PhysicsDebugPlugin)
+        .add_plugins(console::ConsolePlugin)
         
         
         // ----------  Menu Enter ----------
+        .add_plugins(menu::MenuPlugin)
+        .add_plugins(settings_menu::SettingsMenuPlugin)
         
         // ----------  Menu Exit ----------
         
@@ -54,6 +110,9 @@ fn main() {
         // ----------  Pause Enter ----------
         
         // ----------  Pause Exit ----------
+
+        // ----------  Editor ----------
+        .add_plugins(editor::EditorPlugin)
         
         // ----------  Exit Setup ----------
         .run();
@@ -71,20 +130,17 @@ fn startup_setup(
 }
 
 fn debugging_ctrls(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player_transform: Query<(&mut Transform, &mut Position),With<crate::player::Player>>,
-    colliders: Query<(&Collider, &GlobalTransform)>,
-
+    current_level: Res<level::CurrentLevel>,
+    mut players: Query<(&player::CoinInput, &level::LastCheckpoint, &mut Transform, &mut Position)>,
 ) {
     // RESET
-    if keyboard_input.just_pressed(KeyCode::R) {
-        for (mut transfrom, mut position) in player_transform.iter_mut() {
-            transfrom.translation = Vec3::ZERO + SPAWN_POINT;
-            transfrom.rotation = Quat::IDENTITY;
-            position.0 = (Vec3::ZERO + SPAWN_POINT).into();
-        }
-        for (collider, transform) in colliders.iter() {
-            println!("collider: {:?}, position: {:?}", collider, transform.translation());
+    for (input, checkpoint, mut transfrom, mut position) in players.iter_mut() {
+        if !input.reset {
+            continue;
         }
+        let respawn = checkpoint.respawn_point(&current_level);
+        transfrom.translation = respawn;
+        transfrom.rotation = Quat::IDENTITY;
+        position.0 = respawn.into();
     }
 }
\ No newline at end of file
@@ -0,0 +1,283 @@
+use bevy::prelude::*;
+
+use crate::AppState;
+use crate::generator::{daily_course, random_seed};
+use crate::level::{save_level_file, LoadLevel};
+use crate::progress::Progress;
+use crate::race::StartRace;
+use crate::save::{delete_slot, ActiveProfile, SaveProfile, SAVE_SLOTS};
+use crate::skins::{skin_by_id, SelectedSkin, SKINS};
+use crate::settings_menu::OpenSettingsButton;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(OnEnter(AppState::Menu), (menu_setup, show_cursor))
+            .add_systems(OnExit(AppState::Menu), despawn_with::<MenuRoot>)
+            .add_systems(OnEnter(AppState::InGame), hide_cursor)
+            .add_systems(OnEnter(AppState::Paused), (pause_setup, show_cursor, pause_physics))
+            .add_systems(OnExit(AppState::Paused), (despawn_with::<PauseRoot>, resume_physics.run_if(crate::physics_debug::physics_unfrozen)))
+            .add_systems(Update, (
+                menu_actions,
+                skin_label,
+                slot_label,
+            ).run_if(in_state(AppState::Menu)))
+            .add_systems(Update, pause_actions.run_if(in_state(AppState::Paused)))
+            .add_systems(Update, menu_button_colors.run_if(in_state(AppState::Menu).or_else(in_state(AppState::Paused))))
+            .add_systems(Update, toggle_pause)
+            ;
+    }
+}
+
+pub const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+pub const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+pub const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+const MENU_BG: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+
+#[derive(Component)]
+struct MenuRoot;
+
+#[derive(Component)]
+enum MenuButton {
+    Play,
+    Daily,
+    Race,
+    NextSkin,
+    NextSlot,
+    ClearSlot,
+}
+
+#[derive(Component)]
+struct PauseRoot;
+
+#[derive(Component)]
+enum PauseButton {
+    Resume,
+    MainMenu,
+}
+
+#[derive(Component)]
+struct SkinLabel;
+
+#[derive(Component)]
+struct SlotLabel;
+
+pub fn despawn_with<T: Component>(mut commands: Commands, query: Query<Entity, With<T>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn show_cursor(mut windows: Query<&mut Window, With<bevy::window::PrimaryWindow>>) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        window.cursor.visible = true;
+    }
+}
+
+fn hide_cursor(mut windows: Query<&mut Window, With<bevy::window::PrimaryWindow>>) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        window.cursor.visible = false;
+    }
+}
+
+pub fn text_style(asset_server: &AssetServer, font_size: f32) -> TextStyle {
+    TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size,
+        color: TEXT_COLOR,
+    }
+}
+
+pub fn menu_button(parent: &mut ChildBuilder, asset_server: &AssetServer, marker: impl Component, label: &str) {
+    parent.spawn((marker, ButtonBundle {
+        style: Style {
+            width: Val::Px(260.0),
+            height: Val::Px(50.0),
+            margin: UiRect::all(Val::Px(6.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: NORMAL_BUTTON.into(),
+        ..default()
+    })).with_children(|button| {
+        button.spawn(TextBundle::from_section(label, text_style(asset_server, 24.0)));
+    });
+}
+
+fn menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((MenuRoot, NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: MENU_BG.into(),
+        ..default()
+    })).with_children(|parent| {
+        parent.spawn(TextBundle::from_section("I am Coin!", text_style(&asset_server, 60.0)));
+        menu_button(parent, &asset_server, MenuButton::Play, "Play");
+        menu_button(parent, &asset_server, MenuButton::Daily, "Daily course");
+        menu_button(parent, &asset_server, MenuButton::Race, "Race (2 players)");
+        menu_button(parent, &asset_server, OpenSettingsButton, "Settings");
+        menu_button(parent, &asset_server, MenuButton::NextSkin, "Next skin");
+        parent.spawn((SkinLabel, TextBundle::from_section("", text_style(&asset_server, 20.0))));
+        menu_button(parent, &asset_server, MenuButton::NextSlot, "Next save slot");
+        menu_button(parent, &asset_server, MenuButton::ClearSlot, "Clear save slot");
+        parent.spawn((SlotLabel, TextBundle::from_section("", text_style(&asset_server, 20.0))));
+    });
+}
+
+fn pause_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((PauseRoot, NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: MENU_BG.into(),
+        ..default()
+    })).with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Paused", text_style(&asset_server, 60.0)));
+        menu_button(parent, &asset_server, PauseButton::Resume, "Resume");
+        menu_button(parent, &asset_server, OpenSettingsButton, "Settings");
+        menu_button(parent, &asset_server, PauseButton::MainMenu, "Main menu");
+    });
+}
+
+fn pause_physics(mut physics_loop: ResMut<bevy_xpbd_3d::prelude::PhysicsLoop>) {
+    physics_loop.pause();
+}
+
+fn resume_physics(mut physics_loop: ResMut<bevy_xpbd_3d::prelude::PhysicsLoop>) {
+    physics_loop.resume();
+}
+
+fn toggle_pause(
+    keyboard_input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    match state.get() {
+        AppState::InGame => next_state.set(AppState::Paused),
+        AppState::Paused => next_state.set(AppState::InGame),
+        _ => {}
+    }
+}
+
+fn pause_actions(
+    interaction_query: Query<(&Interaction, &PauseButton), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            PauseButton::Resume => next_state.set(AppState::InGame),
+            PauseButton::MainMenu => next_state.set(AppState::Menu),
+        }
+    }
+}
+
+pub fn menu_button_colors(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+) {
+    for (interaction, mut color) in &mut interaction_query {
+        *color = match *interaction {
+            Interaction::Pressed => PRESSED_BUTTON.into(),
+            Interaction::Hovered => HOVERED_BUTTON.into(),
+            Interaction::None => NORMAL_BUTTON.into(),
+        };
+    }
+}
+
+fn menu_actions(
+    interaction_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut selected: ResMut<SelectedSkin>,
+    progress: Res<Progress>,
+    mut active: ResMut<ActiveProfile>,
+    mut load_level: EventWriter<LoadLevel>,
+    mut start_race: EventWriter<StartRace>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::Play => next_state.set(AppState::InGame),
+            MenuButton::Daily => {
+                let level = daily_course();
+                if let Err(err) = save_level_file(&level) {
+                    error!("could not save {}: {:?}", level.name, err);
+                    continue;
+                }
+                load_level.send(LoadLevel(level.name));
+                next_state.set(AppState::InGame);
+            }
+            // switches to InGame itself once the course is up
+            MenuButton::Race => start_race.send(StartRace{seed: random_seed()}),
+            MenuButton::NextSkin => selected.0 = selected.next_unlocked(&progress).id.to_string(),
+            MenuButton::NextSlot => {
+                active.save();
+                *active = ActiveProfile::load((active.slot + 1) % SAVE_SLOTS);
+            }
+            MenuButton::ClearSlot => {
+                if let Err(err) = delete_slot(active.slot) {
+                    error!("could not delete save slot {}: {:?}", active.slot, err);
+                }
+                active.profile = SaveProfile::default();
+            }
+        }
+    }
+}
+
+fn skin_label(
+    selected: Res<SelectedSkin>,
+    progress: Res<Progress>,
+    mut labels: Query<&mut Text, With<SkinLabel>>,
+) {
+    let locked: Vec<String> = SKINS.iter()
+        .filter(|skin| !skin.is_unlocked(&progress))
+        .map(|skin| format!("{} ({})", skin.name, skin.unlock_hint()))
+        .collect();
+    let mut label = format!("Skin: {}", skin_by_id(&selected.0).name);
+    if !locked.is_empty() {
+        label.push_str(&format!("\nLocked: {}", locked.join(", ")));
+    }
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
+
+fn slot_label(
+    active: Res<ActiveProfile>,
+    mut labels: Query<&mut Text, With<SlotLabel>>,
+) {
+    let profile = &active.profile;
+    let label = format!(
+        "Save slot {}: {} levels done, {} collectibles",
+        active.slot + 1,
+        profile.completed_levels.len(),
+        profile.collectibles_found.len(),
+    );
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
@@ -0,0 +1,530 @@
+//! Online races over UDP. The server (`net_server`) owns the simulation: the
+//! clients send it their input, and it sends back snapshots of every coin.
+//! A client still runs its own coin right away so it doesn't feel the round
+//! trip. It only gets snapped to the server's coin once the two drift too
+//! far apart. Everyone else's coins are ghosts, drawn a little in the past
+//! and interpolated between snapshots.
+//!
+//! Messages are RON, one per datagram. There is no reliable channel, every
+//! snapshot carries the whole state (course seed, finish times and all), so a
+//! lost packet is just made up for by the next one.
+//!
+//! `connect <host:port>` joins a server, `netsim` fakes a bad connection.
+
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::coin::{spawn_coin_model, CoinAssets, COIN_HEIGHT, COIN_RADIUS};
+use crate::console::ConsoleAppExt;
+use crate::generator::{generate_course, random_seed, CourseRng};
+use crate::level::CurrentLevel;
+use crate::menu::text_style;
+use crate::player::{read_coin_input, CoinInput, Controlled, JumpEnvelope};
+
+pub struct NetClientPlugin;
+
+impl Plugin for NetClientPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                client_receive,
+                follow_course,
+                reconcile_own_coin,
+                interpolate_ghosts,
+                net_hud,
+            ).chain().run_if(resource_exists::<NetClient>()))
+            .add_systems(Update, client_send_input.after(read_coin_input).run_if(resource_exists::<NetClient>()))
+            .add_systems(OnEnter(AppState::Menu), disconnect_client)
+            .add_console_command("connect", "connect <host:port>: joins an online race", connect)
+            .add_console_command("disconnect", "disconnect: leaves the online race", disconnect)
+            .add_console_command("netsim", "netsim <latency ms> [jitter ms] [loss %]: fakes a bad connection, `netsim 0` turns it off", netsim)
+            ;
+    }
+}
+
+pub const DEFAULT_PORT: u16 = 7878;
+pub const TICK_RATE: f32 = 60.0;
+/// Server ticks between snapshots.
+pub const SNAPSHOT_EVERY: u32 = 3;
+/// Either side gives up on the other after this long without a packet.
+pub const TIMEOUT: f32 = 5.0;
+// ghosts are drawn this far behind the newest snapshot, two snapshots' worth
+// so one can get lost without them stopping
+const INTERPOLATION_DELAY: f32 = 2.5 * SNAPSHOT_EVERY as f32;
+const SNAPSHOT_BUFFER: usize = 32;
+// past this the predicted coin gets put back where the server has it
+const SNAP_DISTANCE: f32 = 2.0;
+const MAX_DATAGRAM: usize = 64 * 1024;
+// a snapshot this many ticks older than the newest means a new server
+const RESTART_GAP: u32 = 10 * TICK_RATE as u32;
+
+/// The part of `CoinInput` that goes over the wire. Presses and releases are
+/// worked out by the server from `jump_held`, so a lost packet can't eat one.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub struct NetInput {
+    pub movement: Vec2,
+    pub jump_held: bool,
+    pub reset: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ClientMessage {
+    /// Sent every frame, and doubles as the keepalive. `sent` is the client's
+    /// clock, echoed back to measure the ping.
+    Input{sent: f32, input: NetInput},
+    Bye,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CoinState {
+    pub id: u32,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub seed: u64,
+    /// The id of the coin the receiver plays.
+    pub you: u32,
+    /// The newest `sent` the server got from the receiver.
+    pub echo: f32,
+    pub coins: Vec<CoinState>,
+    /// Client id and seconds from the start of the course to the goal.
+    pub finishes: Vec<(u32, f32)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ServerMessage {
+    Snapshot(Snapshot),
+    /// Every coin on the server is taken.
+    Full,
+}
+
+/// Makes the connection worse on purpose, for trying things out locally.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LinkConditions {
+    /// Added to every packet, each way.
+    pub latency: Duration,
+    /// Up to this much more, random per packet, so packets also get reordered.
+    pub jitter: Duration,
+    /// Share of packets dropped, 0 to 1.
+    pub loss: f32,
+}
+
+impl LinkConditions {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ms latency, {} ms jitter, {:.0}% loss",
+            self.latency.as_millis(),
+            self.jitter.as_millis(),
+            self.loss * 100.0,
+        )
+    }
+}
+
+struct Delayed {
+    due: Instant,
+    addr: SocketAddr,
+    bytes: Vec<u8>,
+}
+
+fn encode<T: Serialize>(message: &T) -> Option<Vec<u8>> {
+    match ron::to_string(message) {
+        Ok(text) => Some(text.into_bytes()),
+        Err(err) => {
+            error!("could not encode a message: {:?}", err);
+            None
+        }
+    }
+}
+
+/// A non-blocking UDP socket that sends and receives whole messages and
+/// applies `LinkConditions` on the way in and out.
+pub struct NetSocket {
+    socket: UdpSocket,
+    pub conditions: LinkConditions,
+    rng: CourseRng,
+    outgoing: Vec<Delayed>,
+    incoming: Vec<Delayed>,
+}
+
+impl NetSocket {
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self{socket, conditions: LinkConditions::default(), rng: CourseRng(random_seed()), outgoing: Vec::new(), incoming: Vec::new()})
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// When a packet handed over now comes out the other end, `None` if it gets lost.
+    fn due(&mut self) -> Option<Instant> {
+        if self.conditions.loss > 0.0 && self.rng.chance(self.conditions.loss) {
+            return None;
+        }
+        let jitter = self.conditions.jitter.mul_f32(self.rng.next_f32());
+        Some(Instant::now() + self.conditions.latency + jitter)
+    }
+
+    pub fn send<T: Serialize>(&mut self, addr: SocketAddr, message: &T) {
+        let Some(bytes) = encode(message) else { return };
+        let Some(due) = self.due() else { return };
+        self.outgoing.push(Delayed{due, addr, bytes});
+        self.flush();
+    }
+
+    /// Sends right away, past the `LinkConditions`. For a last message when
+    /// the socket is about to go away and nothing would flush it later.
+    pub fn send_now<T: Serialize>(&self, addr: SocketAddr, message: &T) {
+        let Some(bytes) = encode(message) else { return };
+        if let Err(err) = self.socket.send_to(&bytes, addr) {
+            warn!("could not send to {}: {:?}", addr, err);
+        }
+    }
+
+    /// Sends whatever `LinkConditions` held back and is due by now.
+    pub fn flush(&mut self) {
+        let now = Instant::now();
+        let socket = &self.socket;
+        self.outgoing.retain(|packet| {
+            if packet.due > now {
+                return true;
+            }
+            if let Err(err) = socket.send_to(&packet.bytes, packet.addr) {
+                warn!("could not send to {}: {:?}", packet.addr, err);
+            }
+            false
+        });
+    }
+
+    /// Every message that arrived since the last call, oldest first. Anything
+    /// that doesn't parse is dropped.
+    pub fn receive<T: DeserializeOwned>(&mut self) -> Vec<(SocketAddr, T)> {
+        self.flush();
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, addr)) => {
+                    let Some(due) = self.due() else { continue };
+                    self.incoming.push(Delayed{due, addr, bytes: buffer[..len].to_vec()});
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                // on some platforms an unreachable peer shows up here, it's not fatal
+                Err(err) if err.kind() == ErrorKind::ConnectionReset => continue,
+                Err(err) => {
+                    warn!("could not receive: {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        self.incoming.sort_by_key(|packet| packet.due);
+        let ready = self.incoming.iter().take_while(|packet| packet.due <= now).count();
+        self.incoming.drain(..ready)
+            .filter_map(|packet| {
+                let text = std::str::from_utf8(&packet.bytes).ok()?;
+                ron::from_str(text).ok().map(|message| (packet.addr, message))
+            })
+            .collect()
+    }
+}
+
+/// The connection to a server. Exists while connected.
+#[derive(Resource)]
+pub struct NetClient {
+    socket: NetSocket,
+    server: SocketAddr,
+    /// Our coin's id on the server, once the first snapshot is in.
+    id: Option<u32>,
+    seed: Option<u64>,
+    snapshots: VecDeque<Snapshot>,
+    /// The server tick the ghosts are drawn at, fractional.
+    render_tick: f32,
+    /// `Time::elapsed_seconds` when the server was last heard from.
+    last_heard: f32,
+    ping: Option<f32>,
+    full: bool,
+}
+
+/// Someone else's coin. No `Player`, nothing but its position and a collider
+/// so our coin can bump into it.
+#[derive(Component)]
+struct NetGhost(u32);
+
+#[derive(Component)]
+struct NetHud;
+
+pub fn parse_addr(addr: &str) -> Result<SocketAddr, String> {
+    let with_port = if addr.contains(':') { addr.to_string() } else { format!("{}:{}", addr, DEFAULT_PORT) };
+    with_port.to_socket_addrs()
+        .map_err(|err| format!("could not resolve {:?}: {}", addr, err))?
+        .next()
+        .ok_or_else(|| format!("{:?} resolves to nothing", addr))
+}
+
+fn client_receive(time: Res<Time>, mut client: ResMut<NetClient>) {
+    let server = client.server;
+    let now = time.elapsed_seconds();
+    for (from, message) in client.socket.receive::<ServerMessage>() {
+        if from != server {
+            continue;
+        }
+        client.last_heard = now;
+        match message {
+            ServerMessage::Full => client.full = true,
+            ServerMessage::Snapshot(snapshot) => {
+                client.id = Some(snapshot.you);
+                client.ping = Some(now - snapshot.echo);
+                if let Some(newest) = client.snapshots.back().map(|last| last.tick) {
+                    // late or duplicated by the network
+                    if snapshot.tick <= newest && newest - snapshot.tick < RESTART_GAP {
+                        continue;
+                    }
+                    // the server restarted and counts from 0 again
+                    if snapshot.tick < newest {
+                        client.snapshots.clear();
+                    }
+                }
+                client.snapshots.push_back(snapshot);
+                if client.snapshots.len() > SNAPSHOT_BUFFER {
+                    client.snapshots.pop_front();
+                }
+            }
+        }
+    }
+}
+
+fn client_send_input(
+    time: Res<Time>,
+    mut client: ResMut<NetClient>,
+    players: Query<(&CoinInput, &Controlled)>,
+) {
+    let input = players.iter()
+        .find(|(_, controlled)| controlled.seat == 0)
+        .map(|(input, _)| NetInput{movement: input.movement, jump_held: input.jump_held, reset: input.reset})
+        .unwrap_or_default();
+    let server = client.server;
+    client.socket.send(server, &ClientMessage::Input{sent: time.elapsed_seconds(), input});
+}
+
+/// Builds the server's course whenever it moves on to a new one.
+fn follow_course(mut client: ResMut<NetClient>, mut current: ResMut<CurrentLevel>) {
+    let Some(seed) = client.snapshots.back().map(|snapshot| snapshot.seed) else { return };
+    if client.seed == Some(seed) {
+        return;
+    }
+    client.seed = Some(seed);
+    current.0 = Some(generate_course(seed, &JumpEnvelope::default()));
+}
+
+/// Our coin runs locally, the server only steps in once it disagrees by a lot,
+/// like after a reset or a collision that went differently over there.
+fn reconcile_own_coin(
+    client: Res<NetClient>,
+    mut players: Query<(&Controlled, &mut Position, &mut Rotation, &mut LinearVelocity, &mut Transform)>,
+) {
+    let (Some(id), Some(snapshot)) = (client.id, client.snapshots.back()) else { return };
+    let Some(state) = snapshot.coins.iter().find(|coin| coin.id == id) else { return };
+    for (controlled, mut position, mut rotation, mut velocity, mut transform) in players.iter_mut() {
+        if controlled.seat != 0 || position.0.distance(state.position) < SNAP_DISTANCE {
+            continue;
+        }
+        position.0 = state.position;
+        rotation.0 = state.rotation;
+        velocity.0 = state.velocity;
+        transform.translation = state.position;
+        transform.rotation = state.rotation;
+    }
+}
+
+fn interpolate_ghosts(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    coin_assets: Res<CoinAssets>,
+    mut client: ResMut<NetClient>,
+    mut ghosts: Query<(Entity, &NetGhost, &mut Position, &mut Rotation, &mut Transform)>,
+) {
+    let Some(newest) = client.snapshots.back().map(|snapshot| snapshot.tick as f32) else { return };
+    // run at the server's pace, and ease towards the delay when drifting off it
+    let target = newest - INTERPOLATION_DELAY;
+    let mut render_tick = client.render_tick + time.delta_seconds() * TICK_RATE;
+    if (target - render_tick).abs() > 2.0 * INTERPOLATION_DELAY {
+        render_tick = target;
+    } else {
+        render_tick += (target - render_tick) * 0.05;
+    }
+    client.render_tick = render_tick;
+
+    let snapshots = &client.snapshots;
+    let after = snapshots.iter().position(|snapshot| snapshot.tick as f32 > render_tick).unwrap_or(snapshots.len() - 1);
+    let before = after.saturating_sub(1);
+    let (from, to) = (&snapshots[before], &snapshots[after]);
+    let t = if to.tick > from.tick {
+        ((render_tick - from.tick as f32) / (to.tick - from.tick) as f32).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    let state_of = |id: u32| {
+        let end = to.coins.iter().find(|coin| coin.id == id)?;
+        let start = from.coins.iter().find(|coin| coin.id == id).unwrap_or(end);
+        Some((start.position.lerp(end.position, t), start.rotation.slerp(end.rotation, t)))
+    };
+
+    let you = client.id;
+    let mut seen = Vec::new();
+    for (entity, NetGhost(id), mut position, mut rotation, mut transform) in ghosts.iter_mut() {
+        // left the server
+        let Some((at, turned)) = state_of(*id).filter(|_| Some(*id) != you) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        position.0 = at;
+        rotation.0 = turned;
+        transform.translation = at;
+        transform.rotation = turned;
+        seen.push(*id);
+    }
+    for coin in to.coins.iter().filter(|coin| Some(coin.id) != you && !seen.contains(&coin.id)) {
+        commands.spawn((
+            NetGhost(coin.id),
+            SpatialBundle::from_transform(Transform::from_translation(coin.position).with_rotation(coin.rotation)),
+            RigidBody::Kinematic,
+            Position(coin.position),
+            Rotation(coin.rotation),
+            Collider::cylinder(COIN_HEIGHT, COIN_RADIUS),
+        )).with_children(|parent| {
+            spawn_coin_model(parent, &asset_server, &coin_assets);
+        });
+    }
+}
+
+fn net_hud(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    client: Res<NetClient>,
+    mut huds: Query<&mut Text, With<NetHud>>,
+) {
+    let status = if client.full {
+        format!("{} is full", client.server)
+    } else if time.elapsed_seconds() - client.last_heard > TIMEOUT {
+        format!("lost {}", client.server)
+    } else if let (Some(id), Some(snapshot)) = (client.id, client.snapshots.back()) {
+        let mut status = format!(
+            "online as #{}, {} coins, ping {:.0} ms",
+            id,
+            snapshot.coins.len(),
+            client.ping.unwrap_or_default() * 1000.0,
+        );
+        for (finisher, seconds) in snapshot.finishes.iter() {
+            status.push_str(&format!("\n#{} finished in {:.2} s", finisher, seconds));
+        }
+        status
+    } else {
+        format!("connecting to {}", client.server)
+    };
+
+    if huds.is_empty() {
+        commands.spawn((NetHud, TextBundle::from_section(status, text_style(&asset_server, 18.0)).with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        })));
+        return;
+    }
+    for mut text in huds.iter_mut() {
+        text.sections[0].value = status.clone();
+    }
+}
+
+fn leave(world: &mut World) -> bool {
+    let Some(client) = world.remove_resource::<NetClient>() else { return false };
+    // the socket is dropped right after, a delayed Bye would never go out
+    client.socket.send_now(client.server, &ClientMessage::Bye);
+    let leftovers: Vec<Entity> = world.query_filtered::<Entity, Or<(With<NetGhost>, With<NetHud>)>>().iter(world).collect();
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, world);
+    for entity in leftovers {
+        commands.entity(entity).despawn_recursive();
+    }
+    queue.apply(world);
+    true
+}
+
+fn disconnect_client(world: &mut World) {
+    leave(world);
+}
+
+fn connect(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let [addr] = args else { return Err("expected host:port".to_string()) };
+    let server = parse_addr(addr)?;
+    let conditions = world.get_resource::<NetClient>().map(|client| client.socket.conditions).unwrap_or_default();
+    leave(world);
+    let mut socket = NetSocket::bind(("0.0.0.0", 0)).map_err(|err| format!("could not open a socket: {}", err))?;
+    socket.conditions = conditions;
+    let now = world.resource::<Time>().elapsed_seconds();
+    world.insert_resource(NetClient{
+        socket,
+        server,
+        id: None,
+        seed: None,
+        snapshots: VecDeque::new(),
+        render_tick: 0.0,
+        last_heard: now,
+        ping: None,
+        full: false,
+    });
+    Ok(format!("connecting to {}", server))
+}
+
+fn disconnect(world: &mut World, _args: &[&str]) -> Result<String, String> {
+    if leave(world) { Ok("disconnected".to_string()) } else { Err("not connected".to_string()) }
+}
+
+/// Reads `latency [jitter] [loss %]`, shared with the server's command line.
+pub fn parse_conditions(args: &[&str]) -> Result<LinkConditions, String> {
+    let number = |index: usize| -> Result<f32, String> {
+        match args.get(index) {
+            Some(arg) => arg.parse::<f32>().map_err(|_| format!("{:?} is not a number", arg)),
+            None => Ok(0.0),
+        }
+    };
+    let millis = |index: usize| -> Result<Duration, String> {
+        let value = number(index)?;
+        Duration::try_from_secs_f32(value.max(0.0) / 1000.0).map_err(|err| format!("{} ms: {}", value, err))
+    };
+    if args.is_empty() || args.len() > 3 {
+        return Err("expected latency, jitter and loss".to_string());
+    }
+    Ok(LinkConditions{
+        latency: millis(0)?,
+        jitter: millis(1)?,
+        loss: (number(2)? / 100.0).clamp(0.0, 1.0),
+    })
+}
+
+fn netsim(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let conditions = parse_conditions(args)?;
+    let Some(mut client) = world.get_resource_mut::<NetClient>() else {
+        return Err("not connected, connect first".to_string());
+    };
+    client.socket.conditions = conditions;
+    Ok(conditions.describe())
+}
@@ -0,0 +1,363 @@
+//! The dedicated server behind online races, started with `main --server`.
+//! It is the headless app from `headless` with the level plugin and the
+//! systems below on top. Its clock runs at 60 ticks a second in real time.
+//! Every client gets a coin of its own driven by the input it sends, and
+//! every client gets a snapshot of all coins every few ticks.
+//!
+//! ```text
+//! main --server [--port 7878] [--seed n] [--latency ms] [--jitter ms] [--loss %]
+//! ```
+//!
+//! Once everyone connected has reached the goal, the server moves on to the
+//! next seed.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::game_const::PHYSICS_TICK;
+use crate::generator::{generate_course, random_seed};
+use crate::headless::{HeadlessApp, HeadlessAppBuilder};
+use crate::level::{CurrentLevel, LastCheckpoint, LevelCompleted, LevelPlugin};
+use crate::net::*;
+use crate::player::{read_coin_input, spawn_coin, swap_coin, CoinInput, Controlled, JumpEnvelope, RemoteControlled};
+
+pub struct NetServerPlugin;
+
+impl Plugin for NetServerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(PostStartup, drop_local_coins)
+            .add_systems(Update, (
+                start_course.run_if(resource_added::<NetServer>()),
+                server_receive,
+                apply_remote_input,
+            ).chain().after(read_coin_input).before(swap_coin).run_if(resource_exists::<NetServer>()))
+            .add_systems(Update, (record_finishes, next_course).chain().run_if(resource_exists::<NetServer>()))
+            .add_systems(Last, send_snapshots.run_if(resource_exists::<NetServer>()))
+            ;
+    }
+}
+
+const MAX_CLIENTS: usize = 8;
+// pause on the finished course before the next one
+const NEXT_COURSE_DELAY: f32 = 5.0;
+// spacing of the start grid, a coin is 2 wide
+const GRID_SPACING: f32 = 2.5;
+const GRID_COLUMNS: u32 = 4;
+
+struct RemoteClient {
+    id: u32,
+    addr: SocketAddr,
+    coin: Entity,
+    input: NetInput,
+    /// Last tick's buttons, to turn `NetInput`'s held buttons into presses.
+    jump_held: bool,
+    reset_held: bool,
+    /// `Time::elapsed_seconds` of the last packet.
+    last_heard: f32,
+    /// The client's newest `sent`, sent back for its ping.
+    echo: f32,
+}
+
+#[derive(Resource)]
+pub struct NetServer {
+    socket: NetSocket,
+    clients: Vec<RemoteClient>,
+    next_id: u32,
+    tick: u32,
+    seed: u64,
+    /// `Time::elapsed_seconds` the course clock counts from.
+    course_start: f32,
+    finishes: Vec<(u32, f32)>,
+    /// When everyone had finished, the next course comes a bit after.
+    all_finished_at: Option<f32>,
+}
+
+impl NetServer {
+    pub fn new(socket: NetSocket, seed: u64) -> Self {
+        Self{socket, clients: Vec::new(), next_id: 0, tick: 0, seed, course_start: 0.0, finishes: Vec::new(), all_finished_at: None}
+    }
+}
+
+/// Where client `id` starts, on a grid around the course's spawn.
+fn start_spot(spawn: Vec3, id: u32) -> Vec3 {
+    let column = (id % GRID_COLUMNS) as f32 - 0.5 * (GRID_COLUMNS - 1) as f32;
+    let row = ((id / GRID_COLUMNS) % 2) as f32;
+    spawn + Vec3::new(column, 0.0, row) * GRID_SPACING
+}
+
+/// `PlayerPlugin` always spawns a coin for the local seat, a server has no one
+/// sitting at it.
+fn drop_local_coins(mut commands: Commands, coins: Query<Entity, With<Controlled>>) {
+    for coin in coins.iter() {
+        commands.entity(coin).despawn_recursive();
+    }
+}
+
+fn start_course(time: Res<Time>, mut server: ResMut<NetServer>, mut current: ResMut<CurrentLevel>) {
+    server.course_start = time.elapsed_seconds();
+    current.0 = Some(generate_course(server.seed, &JumpEnvelope::default()));
+    info!("course {}", server.seed);
+}
+
+fn server_receive(
+    mut commands: Commands,
+    time: Res<Time>,
+    current: Res<CurrentLevel>,
+    mut server: ResMut<NetServer>,
+) {
+    let now = time.elapsed_seconds();
+    let spawn = current.0.as_ref().map_or(Vec3::ZERO, |level| level.spawn);
+    for (addr, message) in server.socket.receive::<ClientMessage>() {
+        let known = server.clients.iter().position(|client| client.addr == addr);
+        match (message, known) {
+            (ClientMessage::Input{sent, input}, Some(index)) => {
+                let client = &mut server.clients[index];
+                client.last_heard = now;
+                // the newest one wins, a late packet doesn't roll the input back
+                if sent >= client.echo {
+                    client.echo = sent;
+                    client.input = input;
+                }
+            }
+            (ClientMessage::Input{sent, input}, None) => {
+                if server.clients.len() >= MAX_CLIENTS {
+                    server.socket.send(addr, &ServerMessage::Full);
+                    continue;
+                }
+                if server.clients.is_empty() {
+                    // nobody was racing, the clock starts with the first one
+                    server.course_start = now;
+                }
+                let id = server.next_id;
+                server.next_id += 1;
+                let coin = spawn_coin(&mut commands, start_spot(spawn, id));
+                commands.entity(coin).insert(RemoteControlled);
+                server.clients.push(RemoteClient{id, addr, coin, input, jump_held: false, reset_held: false, last_heard: now, echo: sent});
+                info!("#{} joined from {}", id, addr);
+            }
+            (ClientMessage::Bye, Some(index)) => {
+                let client = server.clients.remove(index);
+                commands.entity(client.coin).despawn_recursive();
+                info!("#{} left", client.id);
+            }
+            (ClientMessage::Bye, None) => {}
+        }
+    }
+
+    let mut index = 0;
+    while index < server.clients.len() {
+        if now - server.clients[index].last_heard > TIMEOUT {
+            let client = server.clients.remove(index);
+            commands.entity(client.coin).despawn_recursive();
+            warn!("#{} timed out", client.id);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+fn apply_remote_input(
+    current: Res<CurrentLevel>,
+    mut server: ResMut<NetServer>,
+    mut coins: Query<(&mut CoinInput, &LastCheckpoint, &mut Position, &mut LinearVelocity, &mut Transform), With<RemoteControlled>>,
+) {
+    for client in server.clients.iter_mut() {
+        let Ok((mut input, checkpoint, mut position, mut velocity, mut transform)) = coins.get_mut(client.coin) else { continue };
+        let NetInput{movement, jump_held, reset} = client.input;
+        *input = CoinInput{
+            movement: movement.clamp_length_max(1.0),
+            jump_held,
+            jump_pressed: jump_held && !client.jump_held,
+            jump_released: !jump_held && client.jump_held,
+            ..default()
+        };
+        if reset && !client.reset_held {
+            let respawn = checkpoint.respawn_point(&current);
+            position.0 = respawn;
+            velocity.0 = Vec3::ZERO;
+            transform.translation = respawn;
+            transform.rotation = Quat::IDENTITY;
+        }
+        client.jump_held = jump_held;
+        client.reset_held = reset;
+    }
+}
+
+fn record_finishes(
+    time: Res<Time>,
+    mut server: ResMut<NetServer>,
+    mut completed: EventReader<LevelCompleted>,
+) {
+    let clock = time.elapsed_seconds() - server.course_start;
+    for LevelCompleted{player, ..} in completed.iter() {
+        let Some(id) = server.clients.iter().find(|client| client.coin == *player).map(|client| client.id) else { continue };
+        if server.finishes.iter().any(|(finisher, _)| *finisher == id) {
+            continue;
+        }
+        server.finishes.push((id, clock));
+        info!("#{} finished in {:.2} s", id, clock);
+    }
+}
+
+fn next_course(
+    time: Res<Time>,
+    mut server: ResMut<NetServer>,
+    mut current: ResMut<CurrentLevel>,
+    mut coins: Query<(&mut Position, &mut LinearVelocity, &mut AngularVelocity, &mut Transform, &mut LastCheckpoint), With<RemoteControlled>>,
+) {
+    let now = time.elapsed_seconds();
+    let everyone_finished = !server.clients.is_empty()
+        && server.clients.iter().all(|client| server.finishes.iter().any(|(id, _)| *id == client.id));
+    if !everyone_finished {
+        server.all_finished_at = None;
+        return;
+    }
+    let finished_at = *server.all_finished_at.get_or_insert(now);
+    if now - finished_at < NEXT_COURSE_DELAY {
+        return;
+    }
+
+    server.seed = server.seed.wrapping_add(1);
+    server.finishes.clear();
+    server.all_finished_at = None;
+    server.course_start = now;
+    let level = generate_course(server.seed, &JumpEnvelope::default());
+    for client in server.clients.iter() {
+        let Ok((mut position, mut linear_velocity, mut angular_velocity, mut transform, mut checkpoint)) = coins.get_mut(client.coin) else { continue };
+        let start = start_spot(level.spawn, client.id);
+        position.0 = start;
+        transform.translation = start;
+        transform.rotation = Quat::IDENTITY;
+        linear_velocity.0 = Vec3::ZERO;
+        angular_velocity.0 = Vec3::ZERO;
+        checkpoint.0 = None;
+    }
+    current.0 = Some(level);
+    info!("course {}", server.seed);
+}
+
+fn send_snapshots(
+    mut server: ResMut<NetServer>,
+    coins: Query<(&Position, &Rotation, &LinearVelocity), With<RemoteControlled>>,
+) {
+    server.tick += 1;
+    if server.tick % SNAPSHOT_EVERY != 0 {
+        // still lets delayed packets out on time
+        server.socket.flush();
+        return;
+    }
+    let states: Vec<CoinState> = server.clients.iter()
+        .filter_map(|client| {
+            let (position, rotation, velocity) = coins.get(client.coin).ok()?;
+            Some(CoinState{id: client.id, position: position.0, rotation: rotation.0, velocity: velocity.0})
+        })
+        .collect();
+    let server = &mut *server;
+    for client in server.clients.iter() {
+        let snapshot = Snapshot{
+            tick: server.tick,
+            seed: server.seed,
+            you: client.id,
+            echo: client.echo,
+            coins: states.clone(),
+            finishes: server.finishes.clone(),
+        };
+        server.socket.send(client.addr, &ServerMessage::Snapshot(snapshot));
+    }
+}
+
+/// The server as a headless app, stepped by whoever owns it.
+pub fn server_app(server: NetServer) -> HeadlessApp {
+    let mut sim = HeadlessAppBuilder::new()
+        .with_testmap()
+        .with_plugin(LevelPlugin)
+        .with_plugin(NetServerPlugin)
+        .with_plugin(LogPlugin::default())
+        .build();
+    sim.world().insert_resource(server);
+    sim
+}
+
+fn arg_after(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Runs the dedicated server until the process gets killed. Only comes back
+/// if it can't start.
+pub fn run_server() -> Result<(), String> {
+    let port = match arg_after("--port") {
+        Some(port) => port.parse::<u16>().map_err(|_| format!("{:?} is not a port", port))?,
+        None => DEFAULT_PORT,
+    };
+    let seed = match arg_after("--seed") {
+        Some(seed) => seed.parse::<u64>().map_err(|_| format!("{:?} is not a seed", seed))?,
+        None => random_seed(),
+    };
+    let mut socket = NetSocket::bind(("0.0.0.0", port)).map_err(|err| format!("could not listen on port {}: {}", port, err))?;
+    let conditions: Vec<String> = ["--latency", "--jitter", "--loss"].iter()
+        .map(|flag| arg_after(flag).unwrap_or_else(|| "0".to_string()))
+        .collect();
+    let conditions: Vec<&str> = conditions.iter().map(String::as_str).collect();
+    socket.conditions = parse_conditions(&conditions)?;
+    let addr = socket.local_addr().map_err(|err| err.to_string())?;
+    let conditions = socket.conditions.describe();
+
+    let mut sim = server_app(NetServer::new(socket, seed));
+    info!("listening on {}, {}", addr, conditions);
+    let tick = Duration::from_secs_f32(PHYSICS_TICK);
+    let mut next = Instant::now();
+    loop {
+        sim.step();
+        next += tick;
+        let now = Instant::now();
+        if next > now {
+            std::thread::sleep(next - now);
+        } else {
+            // fell behind, don't try to catch up in a burst
+            next = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 7;
+    // let the coin land on the course before driving it
+    const LANDED_TICK: u32 = 90;
+
+    #[test]
+    fn server_runs_remote_coin() {
+        let socket = NetSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        let mut client = NetSocket::bind("127.0.0.1:0").unwrap();
+        let mut sim = server_app(NetServer::new(socket, SEED));
+        let mut start = None;
+        let mut end = None;
+        for tick in 0..240 {
+            let movement = if tick < LANDED_TICK { Vec2::ZERO } else { Vec2::Y };
+            client.send(server, &ClientMessage::Input{sent: tick as f32 * PHYSICS_TICK, input: NetInput{movement, ..default()}});
+            sim.step();
+            for (_, message) in client.receive::<ServerMessage>() {
+                let ServerMessage::Snapshot(snapshot) = message else {
+                    panic!("the server turned the client away");
+                };
+                assert_eq!(snapshot.seed, SEED, "the server is on another course");
+                let Some(coin) = snapshot.coins.iter().find(|coin| coin.id == snapshot.you) else { continue };
+                if tick >= LANDED_TICK && start.is_none() {
+                    start = Some(coin.position);
+                }
+                end = Some(coin.position);
+            }
+        }
+        let (start, end) = start.zip(end).expect("no snapshot with the client's coin came back");
+        let moved = Vec2::new(end.x - start.x, end.z - start.z).length();
+        assert!(moved > 1.0, "the coin only moved {} on the server", moved);
+    }
+}
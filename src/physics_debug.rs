@@ -0,0 +1,173 @@
+//! Slow motion and frame stepping for the physics only, rendering and input
+//! keep running. F6 freezes the physics, F7 runs a single tick, F8 and F9
+//! halve and double the time scale. Works in game and from the pause menu,
+//! and turns the debug overlay on so the coin's state is visible.
+//!
+//! xpbd's fixed timestep always keeps up with the wall clock, so away from
+//! 1x the physics loop stays paused and gets as many fixed ticks queued each
+//! frame as the scaled clock has run. The tick itself never changes.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use bevy_xpbd_3d::PhysicsSchedule;
+
+use crate::AppState;
+use crate::console::ConsoleAppExt;
+use crate::debug_overlay::DebugOverlay;
+use crate::game_const::PHYSICS_TICK;
+
+pub struct PhysicsDebugPlugin;
+
+impl Plugin for PhysicsDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PhysicsDebug>()
+            // the time scale only ever changes how many of these run a frame
+            .insert_resource(PhysicsTimestep::Fixed(PHYSICS_TICK))
+            .add_systems(Update, physics_debug_keys.run_if(in_state(AppState::InGame).or_else(in_state(AppState::Paused))))
+            .add_systems(Update, apply_timescale.after(physics_debug_keys).run_if(in_state(AppState::InGame)))
+            .add_systems(PhysicsSchedule, count_steps)
+            .add_console_command("timescale", "timescale s: runs the physics at s times normal speed, 1 goes back to the fixed tick", timescale)
+            .add_console_command("step", "step [n]: freezes the physics and runs n ticks", step)
+            ;
+    }
+}
+
+const MIN_TIMESCALE: f32 = 1.0 / 16.0;
+const MAX_TIMESCALE: f32 = 4.0;
+
+#[derive(Resource, Debug)]
+pub struct PhysicsDebug {
+    /// Frozen by the stepper, separate from the pause menu freezing it.
+    pub frozen: bool,
+    pub timescale: f32,
+    /// Physics ticks run since startup.
+    pub steps: u64,
+    /// Scaled time not stepped yet, `Some` while the time scale holds the
+    /// physics loop paused.
+    scaled_time: Option<f32>,
+}
+
+impl Default for PhysicsDebug {
+    fn default() -> Self {
+        Self{frozen: false, timescale: 1.0, steps: 0, scaled_time: None}
+    }
+}
+
+impl PhysicsDebug {
+    pub fn describe(&self) -> String {
+        format!(
+            "physics {} x{:.3}{}, tick {}",
+            if self.frozen { "frozen" } else { "running" },
+            self.timescale,
+            if self.timescale == 1.0 { "" } else { " stepped" },
+            self.steps,
+        )
+    }
+}
+
+fn physics_debug_keys(
+    keyboard_input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut physics_debug: ResMut<PhysicsDebug>,
+    mut physics_loop: ResMut<PhysicsLoop>,
+    mut overlay: ResMut<DebugOverlay>,
+) {
+    let freeze = keyboard_input.just_pressed(KeyCode::F6);
+    let step = keyboard_input.just_pressed(KeyCode::F7);
+    let slower = keyboard_input.just_pressed(KeyCode::F8);
+    let faster = keyboard_input.just_pressed(KeyCode::F9);
+    if !(freeze || step || slower || faster) {
+        return;
+    }
+
+    if freeze {
+        physics_debug.frozen = !physics_debug.frozen;
+        // the pause menu keeps the physics paused until it closes
+        if physics_debug.frozen {
+            physics_loop.pause();
+        } else if *state.get() == AppState::InGame {
+            physics_loop.resume();
+        }
+    }
+    if step {
+        if !physics_debug.frozen && *state.get() == AppState::InGame {
+            physics_debug.frozen = true;
+            physics_loop.pause();
+        }
+        physics_loop.step();
+    }
+    if slower {
+        physics_debug.timescale = (physics_debug.timescale * 0.5).max(MIN_TIMESCALE);
+    }
+    if faster {
+        physics_debug.timescale = (physics_debug.timescale * 2.0).min(MAX_TIMESCALE);
+    }
+    if !overlay.enabled {
+        overlay.enabled = true;
+    }
+}
+
+fn apply_timescale(
+    time: Res<Time>,
+    mut physics_debug: ResMut<PhysicsDebug>,
+    mut physics_loop: ResMut<PhysicsLoop>,
+) {
+    if physics_debug.frozen {
+        return;
+    }
+    let timescale = physics_debug.timescale;
+    if timescale == 1.0 {
+        // back on xpbd's own accumulator
+        if physics_debug.scaled_time.take().is_some() {
+            physics_loop.resume();
+        }
+        return;
+    }
+    // paused, or xpbd feeds the wall clock in on top of the queued ticks
+    physics_loop.pause();
+    let scaled_time = physics_debug.scaled_time.get_or_insert(0.0);
+    *scaled_time += time.delta_seconds() * timescale;
+    while *scaled_time >= PHYSICS_TICK {
+        physics_loop.step();
+        *scaled_time -= PHYSICS_TICK;
+    }
+}
+
+fn count_steps(mut physics_debug: ResMut<PhysicsDebug>) {
+    physics_debug.steps += 1;
+}
+
+/// Whether something else may resume the physics, like closing the pause menu.
+pub fn physics_unfrozen(physics_debug: Res<PhysicsDebug>) -> bool {
+    !physics_debug.frozen
+}
+
+fn timescale(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let [scale] = crate::console::parse_args(args)?;
+    if scale <= 0.0 {
+        return Err("the time scale has to be above 0".to_string());
+    }
+    let scale = scale.clamp(MIN_TIMESCALE, MAX_TIMESCALE);
+    world.resource_mut::<PhysicsDebug>().timescale = scale;
+    if scale == 1.0 {
+        Ok("physics back on its fixed tick".to_string())
+    } else {
+        Ok(format!("physics time scale {}, stepped from a scaled clock", scale))
+    }
+}
+
+fn step(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let ticks = match args.first() {
+        Some(arg) => arg.parse::<u32>().map_err(|_| format!("{:?} is not a tick count", arg))?,
+        None => 1,
+    };
+    world.resource_mut::<PhysicsDebug>().frozen = true;
+    let mut physics_loop = world.resource_mut::<PhysicsLoop>();
+    physics_loop.pause();
+    for _ in 0..ticks {
+        physics_loop.step();
+    }
+    world.resource_mut::<DebugOverlay>().enabled = true;
+    Ok(format!("stepping {} ticks", ticks))
+}
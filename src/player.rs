@@ -8,18 +8,29 @@
 
 use std::f32::consts::PI;
 
-use bevy::{prelude::*, utils::hashbrown::raw::Global};
+use bevy::prelude::*;
 use bevy_xpbd_3d::{math::*, prelude::*, PhysicsSchedule, PhysicsStepSet};
+use crate::camera::CameraRig;
 use crate::game_const::*;
+use crate::helpers::YRotation;
+use crate::level::LastCheckpoint;
+use crate::settings::{Action, KeyBindings, SecondPlayerBindings};
 
 pub struct PlayerPlugin;  
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app
-            //.init_resource::<JumpStrength>()
+            .init_resource::<JumpTuning>()
+            .init_resource::<MovementTuning>()
+            .init_resource::<ControlsLocked>()
+            .add_event::<PlayerJumped>()
+            .add_event::<PlayerLanded>()
+            .add_event::<PlayerFlipped>()
             .add_systems(Startup, setup)
-            .add_systems(PhysicsSchedule, movement.before(PhysicsStepSet::BroadPhase))
+            .add_systems(Update, ((read_coin_input, swap_coin, update_ground_state, update_wall_state, wall_jump, jump, wall_slide).chain(), detect_flip))
+            .insert_resource(SubstepCount(BASE_SUBSTEPS))
+            .add_systems(PhysicsSchedule, (movement, adapt_substeps, sweep_fast_players).chain().before(PhysicsStepSet::BroadPhase))
             ;
     }
 }
@@ -27,16 +38,250 @@ impl Plugin for PlayerPlugin {
 #[derive(Component)]
 pub struct Player;
 
+/// Collision layers. Colliders without `CollisionLayers` are on all of them.
+#[derive(PhysicsLayer)]
+pub enum Layer {
+    /// Trigger volumes like checkpoints, goals and plates. They still see
+    /// everything, but the coin's casts look straight through them.
+    Sensor,
+}
+
+/// For a collider that things pass through.
+pub fn sensor() -> (Sensor, CollisionLayers) {
+    (Sensor, CollisionLayers::all_masks::<Layer>().add_group(Layer::Sensor))
+}
+
+/// What the coin's casts hit: anything solid, no trigger volumes.
+pub fn solid_filter() -> SpatialQueryFilter {
+    SpatialQueryFilter::new().with_masks_from_bits(!Layer::Sensor.to_bits())
+}
+
+/// A coin someone is playing. Seat 0 is the keyboard and mouse, seat 1 the
+/// second split screen player. The swap key hands it to the next free coin.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Controlled {
+    pub seat: usize,
+}
+
+/// A coin played from another machine. The seats leave it alone, its
+/// `CoinInput` gets filled in by the network after `read_coin_input`.
+#[derive(Component, Debug)]
+pub struct RemoteControlled;
+
+/// What the seat playing a coin asks of it this frame. Coins nobody plays
+/// keep the default, no input at all.
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct CoinInput {
+    /// x is right and y is forward, at most length 1.
+    pub movement: Vec2,
+    /// Camera turn from a right stick, x is yaw and y is pitch.
+    pub look: Vec2,
+    pub jump_held: bool,
+    pub jump_pressed: bool,
+    pub jump_released: bool,
+    pub swap: bool,
+    pub reset: bool,
+}
+
+/// One of the two shape casters under each face of the coin. The cast
+/// direction is local, so after a flip the `Up` caster is the one looking
+/// at the floor.
 #[derive(Component)]
 pub struct PlayerJump {
-    dir: Direction
+    pub dir: Direction,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Direction {
     Up,
     Down,
 }
 
+/// Heads is the coin's local +Y face.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Face {
+    Heads,
+    Tails,
+}
+
+/// What the coin is standing on, if anything.
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct GroundState {
+    /// The face touching the ground. `None` while airborne or on the rim.
+    pub face: Option<Face>,
+    pub normal: Vec3,
+    /// Angle between the ground normal and straight up, in radians.
+    pub slope_angle: f32,
+    pub entity: Option<Entity>,
+}
+
+impl GroundState {
+    pub fn grounded(&self) -> bool {
+        self.face.is_some()
+    }
+
+    pub fn walkable(&self, max_slope: f32) -> bool {
+        self.grounded() && self.slope_angle <= max_slope
+    }
+}
+
+/// A wall the coin is touching with its rim or brushing against.
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct WallState {
+    /// Points away from the wall. `None` when there is no wall.
+    pub normal: Option<Vec3>,
+    pub entity: Option<Entity>,
+    /// Seconds until the next wall jump is allowed.
+    pub cooldown: f32,
+}
+
+/// How hard input pushes the coin and how quickly it slows down.
+#[derive(Clone, Copy, Debug)]
+pub struct MoveParams {
+    pub accel: f32,
+    pub drag: f32,
+    /// Input stops adding speed past this, but never takes speed away.
+    pub max_speed: f32,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct MovementTuning {
+    pub ground: MoveParams,
+    pub air: MoveParams,
+    /// How much spin curves the coin in the air. 0 turns it off.
+    pub magnus: f32,
+    /// Slope angle in radians past which ground grip is lost.
+    pub max_slope: f32,
+    /// Downhill acceleration on a slope, scaled by how steep it is.
+    pub slope_accel: f32,
+}
+
+impl Default for MovementTuning {
+    fn default() -> Self {
+        Self{
+            ground: MoveParams{accel: GROUND_ACCEL, drag: GROUND_DRAG, max_speed: GROUND_MAX_SPEED},
+            air: MoveParams{accel: AIR_ACCEL, drag: AIR_DRAG, max_speed: AIR_MAX_SPEED},
+            magnus: MAGNUS_COEFFICIENT,
+            max_slope: MAX_WALKABLE_SLOPE,
+            slope_accel: SLOPE_ACCEL,
+        }
+    }
+}
+
+/// While set the coin ignores movement and jump input, for when something
+/// else is using those keys.
+#[derive(Resource, Default, Debug)]
+pub struct ControlsLocked(pub bool);
+
+/// Forgiveness windows around a jump, in seconds.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct JumpTuning {
+    /// How long after leaving the ground a jump still counts as grounded.
+    pub coyote_time: f32,
+    /// How long a jump released in the air is remembered before landing.
+    pub buffer_time: f32,
+    /// Cheat, every release jumps even in mid air.
+    pub jump_anywhere: bool,
+}
+
+impl Default for JumpTuning {
+    fn default() -> Self {
+        Self{coyote_time: COYOTE_TIME, buffer_time: JUMP_BUFFER_TIME, jump_anywhere: false}
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct JumpTimers {
+    /// Seconds since a face last touched the ground. Infinite right after a jump
+    /// so coyote time can't be used for a second one.
+    pub since_grounded: f32,
+    pub last_ground_normal: Vec3,
+    /// Seconds left on a buffered jump, and how strong it was.
+    pub buffered: f32,
+    pub buffered_strength: f32,
+    /// A wall jump used up the press, the release after it does nothing.
+    pub release_spent: bool,
+}
+
+impl Default for JumpTimers {
+    fn default() -> Self {
+        Self{since_grounded: f32::INFINITY, last_ground_normal: Vec3::Y, buffered: 0.0, buffered_strength: 0.0, release_spent: false}
+    }
+}
+
+/// What a jump can clear, worked out from the jump strength, gravity and top
+/// speed. Generated courses stay inside it.
+#[derive(Clone, Copy, Debug)]
+pub struct JumpEnvelope {
+    /// Take off speed of a fully charged jump.
+    pub jump_speed: f32,
+    /// Downward acceleration on the coin, gravity scale included.
+    pub gravity: f32,
+    /// Horizontal speed carried through the air.
+    pub run_speed: f32,
+}
+
+impl JumpEnvelope {
+    pub fn new(gravity: f32, tuning: &MovementTuning) -> Self {
+        Self{
+            // a full charge doubles the base strength, see `jump`
+            jump_speed: BASE_JUMP_STRNGTH * 2.0,
+            gravity: gravity * PLAYER_GRAVITY_SCALE,
+            run_speed: tuning.ground.max_speed.min(tuning.air.max_speed),
+        }
+    }
+
+    pub fn max_height(&self) -> f32 {
+        self.jump_speed * self.jump_speed / (2.0 * self.gravity)
+    }
+
+    /// Horizontal distance covered before coming back down to `rise` above
+    /// the take off point, or None if the jump doesn't get that high.
+    pub fn max_distance(&self, rise: f32) -> Option<f32> {
+        let discriminant = self.jump_speed * self.jump_speed - 2.0 * self.gravity * rise;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let air_time = (self.jump_speed + discriminant.sqrt()) / self.gravity;
+        Some(self.run_speed * air_time)
+    }
+}
+
+impl Default for JumpEnvelope {
+    fn default() -> Self {
+        Self::new(GRAVITY, &MovementTuning::default())
+    }
+}
+
+/// How long the jump has been charged, in seconds up to `MAX_JUMP_TIME_LENGTH`.
+#[derive(Component, Default, Debug)]
+pub struct JumpStrength(pub f32);
+
+#[derive(Event)]
+pub struct PlayerJumped {
+    pub player: Entity,
+    pub strength: f32,
+}
+
+/// The face currently pointing up.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FaceUp(pub Face);
+
+#[derive(Event)]
+pub struct PlayerFlipped {
+    pub player: Entity,
+    /// The face that is up after the flip.
+    pub face: Face,
+}
+
+#[derive(Event)]
+pub struct PlayerLanded {
+    pub player: Entity,
+    /// The face that hit the ground.
+    pub face: Face,
+    pub impact_speed: f32,
+}
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -65,28 +310,50 @@ fn setup(
         Collider::cuboid(8.0, 0.005, 8.0),
     ));
 
-    // Player
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Cylinder {
-                radius: 1.0,
-                height: 0.2,
-                ..default()
-            })),
-            material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
+    let player = spawn_coin(&mut commands, SPAWN_POINT);
+    commands.entity(player).insert(Controlled{seat: 0});
+
+    // Light
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 1500.0,
+            shadows_enabled: true,
             ..default()
         },
+        transform: Transform::from_xyz(4.0, 8.0, 4.0),
+        ..default()
+    });
+
+    // Camera, moved around by the CameraRigPlugin
+    commands.spawn((Camera3dBundle {
+        transform: Transform::from_xyz(-4.0, 6.5, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    }, CameraRig::default()));
+}
+
+/// A coin with its ground casters. The model gets attached by the CoinPlugin.
+pub fn spawn_coin(commands: &mut Commands, position: Vec3) -> Entity {
+    commands.spawn((
+        SpatialBundle::from_transform(Transform::from_translation(position)),
         RigidBody::Dynamic,
-        Position(Vector::Y * 1.0),
-        Collider::cylinder(0.2, 1.0),
+        Position(position),
+        Collider::cylinder(crate::coin::COIN_HEIGHT, crate::coin::COIN_RADIUS),
         // Prevent the player from falling over
         //LockedAxes::new().lock_rotation_x().lock_rotation_z(),
         // Cast the player shape downwards to detect when the player is grounded
         
         
         Restitution::new(0.0).with_combine_rule(CoefficientCombine::Min),
-        GravityScale(2.0),
+        GravityScale(PLAYER_GRAVITY_SCALE),
         Player,
+        FaceUp(Face::Heads),
+        GroundState::default(),
+        WallState::default(),
+        JumpTimers::default(),
+        JumpStrength::default(),
+        YRotation::default(),
+        CoinInput::default(),
+        LastCheckpoint::default(),
     )).with_children(|parent| {
         parent.spawn(
             (
@@ -97,6 +364,7 @@ fn setup(
                     Quaternion::default(),
                     Vector::NEG_Y,
                 ).with_ignore_origin_penetration(true) // Don't count player's collider
+                .with_query_filter(solid_filter())
                 .with_max_time_of_impact(0.2)
                 .with_max_hits(1),
             )
@@ -110,93 +378,647 @@ fn setup(
                     Quaternion::default(),
                     Vector::Y,
                 ).with_ignore_origin_penetration(true) // Don't count player's collider
+                .with_query_filter(solid_filter())
                 .with_max_time_of_impact(0.2)
                 .with_max_hits(1),
             )
         );
-    });
+    }).id()
+}
 
-    // Light
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            intensity: 1500.0,
-            shadows_enabled: true,
-            ..default()
-        },
-        transform: Transform::from_xyz(4.0, 8.0, 4.0),
-        ..default()
-    });
+/// Control along whatever the coin is on. On the ground it's grippy with a lot
+/// of drag, in the air the coin keeps its momentum and input only steers it a
+/// little. Slopes pull the coin downhill, and past `max_slope` there's no grip
+/// left so it slides.
+pub fn movement(
+    tuning: Res<MovementTuning>,
+    delta_time: Res<DeltaTime>,
+    rigs: Query<&CameraRig>,
+    mut players: Query<(&CoinInput, Option<&Controlled>, &GroundState, &AngularVelocity, &mut LinearVelocity), With<Player>>,
+) {
+    let dt = delta_time.0;
+    for (coin_input, controlled, ground, angular_velocity, mut linear_velocity) in &mut players {
+        let walkable = ground.walkable(tuning.max_slope);
+        let params = if walkable { tuning.ground } else { tuning.air };
+        let up = if ground.grounded() { ground.normal } else { Vec3::Y };
+        // without input drag and slopes still apply
+        // forward is where the seat's camera looks, coins without one go by the world axes
+        let yaw = controlled
+            .and_then(|controlled| rigs.iter().find(|rig| rig.seat == controlled.seat))
+            .map_or(0.0, |rig| rig.yaw);
+        let input = Quat::from_rotation_y(yaw) * Vec3::new(coin_input.movement.x, 0.0, -coin_input.movement.y);
+        let wish = input.reject_from(up).normalize_or_zero();
+        let mut planar = linear_velocity.0.reject_from(up);
+        let off_plane = linear_velocity.0 - planar;
 
-    // Camera
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(-4.0, 6.5, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
-    });
+        // only add as much as keeps the speed along the input under the cap
+        if wish != Vec3::ZERO && (walkable || !ground.grounded()) {
+            let along = planar.dot(wish);
+            let add = (params.max_speed - along).clamp(0.0, params.accel * dt);
+            planar += wish * add;
+        }
+        // steeper ground grips less
+        let grip = if walkable { 1.0 - ground.slope_angle / tuning.max_slope } else { 1.0 };
+        planar *= (-params.drag * grip * dt).exp();
+
+        if ground.grounded() {
+            let downhill = Vec3::NEG_Y.reject_from(up).normalize_or_zero();
+            planar += downhill * tuning.slope_accel * ground.slope_angle.sin() * dt;
+        } else if tuning.magnus > 0.0 {
+            // a spinning coin curves through the air
+            let curve = angular_velocity.0.cross(linear_velocity.0) * tuning.magnus * dt;
+            planar += Vec3::new(curve.x, 0.0, curve.z);
+        }
+
+        linear_velocity.0 = planar + off_plane;
+    }
 }
 
-fn movement(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut players: Query<&mut LinearVelocity>,
-    mut jump_query: Query<(&ShapeHits, &PlayerJump)>,
-    query_player_transform: Query<&GlobalTransform, With<Player>>,
-    mut motion_evr: EventReader<bevy::input::mouse::MouseMotion>,
+/// More substeps when the player is fast, so contacts against thin walls get
+/// resolved before the coin is already past them.
+fn adapt_substeps(
+    players: Query<&LinearVelocity, With<Player>>,
+    mut substeps: ResMut<SubstepCount>,
+) {
+    let speed = players.iter().map(|v| v.length()).fold(0.0, f32::max);
+    let wanted = if speed > CCD_SPEED {
+        ((BASE_SUBSTEPS as f32 * speed / CCD_SPEED) as u32).min(MAX_SUBSTEPS)
+    } else {
+        BASE_SUBSTEPS
+    };
+    if substeps.0 != wanted {
+        substeps.0 = wanted;
+    }
+}
+
+/// Casts the coin along its velocity for this step. If it would hit something,
+/// the velocity into that surface is cut down to just reach it, so even a
+/// hard launch can't skip over a wall thinner than one step of movement.
+fn sweep_fast_players(
+    delta_time: Res<DeltaTime>,
+    spatial_query: SpatialQuery,
+    mut players: Query<(Entity, &Position, &Rotation, &Collider, &mut LinearVelocity), With<Player>>,
 ) {
-    for (mut linear_velocity) in &mut players {
-        // Directional movement
-        let mut velocity_change = Vec3::ZERO;
-        if keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up) {
-            velocity_change.z -= 1.2;
+    let dt = delta_time.0;
+    for (entity, position, rotation, collider, mut linear_velocity) in players.iter_mut() {
+        let speed = linear_velocity.length();
+        if speed <= CCD_SPEED || dt <= 0.0 {
+            continue;
         }
-        if keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down) {
-            velocity_change.z += 1.2;
+        let direction = linear_velocity.0 / speed;
+        let Some(hit) = spatial_query.cast_shape(
+            collider,
+            position.0,
+            rotation.0,
+            direction,
+            speed * dt,
+            true,
+            solid_filter().without_entities([entity]),
+        ) else {
+            continue;
+        };
+        let normal = hit.normal1;
+        let into_surface = linear_velocity.dot(normal);
+        if into_surface < 0.0 {
+            let allowed = -hit.time_of_impact / dt;
+            linear_velocity.0 -= normal * (into_surface - allowed);
         }
-        if keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left) {
-            velocity_change.x -= 1.2;
+    }
+}
+
+/// Hold jump to charge while grounded, release to jump off along the ground normal.
+/// Releasing just after leaving the ground still jumps (coyote time), releasing
+/// just before landing jumps on touchdown (buffering).
+fn jump(
+    time: Res<Time>,
+    tuning: Res<JumpTuning>,
+    mut players: Query<(Entity, &CoinInput, &GroundState, &mut JumpTimers, &mut JumpStrength, &mut LinearVelocity), With<Player>>,
+    mut jumped: EventWriter<PlayerJumped>,
+) {
+    let dt = time.delta_seconds();
+    for (player, input, ground, mut timers, mut jump_strength, mut linear_velocity) in players.iter_mut() {
+        let pressed = input.jump_held;
+        let released = input.jump_released;
+        let strength = BASE_JUMP_STRNGTH * (1.0 + jump_strength.0 / MAX_JUMP_TIME_LENGTH);
+        if ground.grounded() {
+            timers.since_grounded = 0.0;
+            timers.last_ground_normal = ground.normal;
+        } else {
+            timers.since_grounded += dt;
         }
-        if keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right) {
-            velocity_change.x += 1.2;
+        timers.buffered = (timers.buffered - dt).max(0.0);
+        if timers.release_spent {
+            timers.release_spent = !released;
+            jump_strength.0 = 0.0;
+            continue;
         }
 
-        // Jump if space pressed and the player is close enough to the ground
-        for (ground_hits, player_jump) in jump_query.iter() {
-            if keyboard_input.just_pressed(KeyCode::Space) && !ground_hits.is_empty() {
-                match player_jump.dir {
-                    Direction::Down => linear_velocity.y += 8.0,
-                    Direction::Up => linear_velocity.y += 8.0,
-                };
+        let can_jump = ground.grounded() || timers.since_grounded <= tuning.coyote_time || tuning.jump_anywhere;
+
+        let jump_with = if released && can_jump {
+            Some(strength)
+        } else if released {
+            timers.buffered = tuning.buffer_time;
+            timers.buffered_strength = strength;
+            None
+        } else if timers.buffered > 0.0 && ground.grounded() {
+            timers.buffered = 0.0;
+            Some(timers.buffered_strength)
+        } else {
+            None
+        };
+
+        if let Some(strength) = jump_with {
+            // a buffered jump can fire while still falling onto the ground,
+            // the fall mustn't eat into it
+            let normal = timers.last_ground_normal;
+            linear_velocity.0 = linear_velocity.0.reject_from(normal) + normal * strength;
+            timers.since_grounded = f32::INFINITY;
+            jumped.send(PlayerJumped { player, strength });
+        }
+
+        if released {
+            jump_strength.0 = 0.0;
+        } else if pressed && can_jump {
+            jump_strength.0 = (jump_strength.0 + dt).min(MAX_JUMP_TIME_LENGTH);
+        }
+    }
+}
+
+/// Fills in every coin's `CoinInput` from the keys and gamepad of the seat
+/// playing it. Gamepads go to seats by id, which is the order they were
+/// connected in. In split screen a lone gamepad goes to seat 1, seat 0 still
+/// has the main keys and the mouse.
+pub fn read_coin_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    second_bindings: Res<SecondPlayerBindings>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    locked: Res<ControlsLocked>,
+    mut players: Query<(&mut CoinInput, Option<&Controlled>), (With<Player>, Without<RemoteControlled>)>,
+) {
+    // `Gamepads` is a hash map, without sorting the seats could swap pads
+    let mut pads: Vec<Gamepad> = gamepads.iter().collect();
+    pads.sort_by_key(|pad| pad.id);
+    let two_seats = players.iter().any(|(_, controlled)| controlled.map_or(false, |c| c.seat == 1));
+    let mut seat_pads: Vec<Option<Gamepad>> = pads.into_iter().map(Some).collect();
+    if two_seats && seat_pads.len() == 1 {
+        seat_pads.insert(0, None);
+    }
+    // one keyboard, two players: the arrows can't drive both coins
+    let first_bindings = if two_seats { bindings.without_keys(&second_bindings.0.all_keys()) } else { bindings.clone() };
+
+    for (mut input, controlled) in players.iter_mut() {
+        let Some(controlled) = controlled.filter(|_| !locked.0) else {
+            *input = CoinInput::default();
+            continue;
+        };
+        let keys = if controlled.seat == 0 { &first_bindings } else { &second_bindings.0 };
+        let pad = seat_pads.get(controlled.seat).copied().flatten();
+        let stick = |x, y| pad.map_or(Vec2::ZERO, |pad| {
+            let value = Vec2::new(
+                axes.get(GamepadAxis::new(pad, x)).unwrap_or(0.0),
+                axes.get(GamepadAxis::new(pad, y)).unwrap_or(0.0),
+            );
+            if value.length() > GAMEPAD_DEADZONE { value } else { Vec2::ZERO }
+        });
+        let button = |button_type| pad.map(|pad| GamepadButton::new(pad, button_type));
+        let held = |action, button_type| keys.pressed(action, &keyboard_input)
+            || button(button_type).map_or(false, |button| buttons.pressed(button));
+        let pressed = |action, button_type| keys.just_pressed(action, &keyboard_input)
+            || button(button_type).map_or(false, |button| buttons.just_pressed(button));
+
+        let mut movement = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+        for (action, dpad, direction) in [
+            (Action::Forward, GamepadButtonType::DPadUp, Vec2::Y),
+            (Action::Back, GamepadButtonType::DPadDown, Vec2::NEG_Y),
+            (Action::Left, GamepadButtonType::DPadLeft, Vec2::NEG_X),
+            (Action::Right, GamepadButtonType::DPadRight, Vec2::X),
+        ] {
+            if held(action, dpad) {
+                movement += direction;
             }
         }
 
-        let sens_x = 0.5;
-        let sens_y = 0.5;
+        *input = CoinInput{
+            movement: movement.clamp_length_max(1.0),
+            look: stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+            jump_held: held(Action::Jump, GamepadButtonType::South),
+            jump_pressed: pressed(Action::Jump, GamepadButtonType::South),
+            jump_released: keys.just_released(Action::Jump, &keyboard_input)
+                || button(GamepadButtonType::South).map_or(false, |button| buttons.just_released(button)),
+            swap: pressed(Action::SwapCoin, GamepadButtonType::North),
+            reset: pressed(Action::Reset, GamepadButtonType::Select),
+        };
+    }
+}
 
+/// Hands a seat's coin over to the next coin nobody is playing, in spawn
+/// order. Also gives seat 0 a coin when it has none, like after the one it
+/// played went away with its level.
+pub fn swap_coin(
+    mut commands: Commands,
+    players: Query<(Entity, &CoinInput, Option<&Controlled>), (With<Player>, Without<RemoteControlled>)>,
+) {
+    let mut coins: Vec<(Entity, Option<usize>, bool)> = players.iter()
+        .map(|(entity, input, controlled)| (entity, controlled.map(|c| c.seat), input.swap))
+        .collect();
+    coins.sort_by_key(|(entity, _, _)| *entity);
 
-        // movement
-        //for ev in motion_evr.iter() {
-            //camera_look.0.x -= ev.delta.x*sens_x*SENS_X;
-            //camera_look.0.y -= ev.delta.y*sens_y*SENS_Y;
+    if !coins.iter().any(|(_, seat, _)| *seat == Some(0)) {
+        if let Some((entity, _, _)) = coins.iter().find(|(_, seat, _)| seat.is_none()) {
+            commands.entity(*entity).insert(Controlled{seat: 0});
+        }
+        return;
+    }
+    for i in 0..coins.len() {
+        let (entity, Some(seat), true) = coins[i] else { continue };
+        let free = (1..coins.len()).map(|step| (i + step) % coins.len()).find(|j| coins[*j].1.is_none());
+        let Some(j) = free else { continue };
+        commands.entity(entity).remove::<Controlled>();
+        commands.entity(coins[j].0).insert(Controlled{seat});
+        coins[i].1 = None;
+        coins[j].1 = Some(seat);
+    }
+}
 
-            //println!("Mouse moved: X: {} px, Y: {} px", ev.delta.x, ev.delta.y);
-        //}
+/// Works out which face is on the ground from the two casters. Only a caster
+/// whose face currently points down counts, so a hit above the coin is a
+/// ceiling and not ground.
+fn update_ground_state(
+    mut players: Query<(Entity, &GlobalTransform, &LinearVelocity, &Children, &mut GroundState), With<Player>>,
+    casters: Query<(&ShapeHits, &PlayerJump)>,
+    mut landed: EventWriter<PlayerLanded>,
+) {
+    for (player, transform, linear_velocity, children, mut ground) in players.iter_mut() {
+        let mut new_ground = GroundState::default();
+        for child in children.iter() {
+            let Ok((ground_hits, player_jump)) = casters.get(*child) else { continue };
+            let (face, local_dir) = match player_jump.dir {
+                Direction::Up => (Face::Heads, Vec3::Y),
+                Direction::Down => (Face::Tails, Vec3::NEG_Y),
+            };
+            let world_dir = transform.affine().transform_vector3(local_dir).normalize_or_zero();
+            if world_dir.y > -GROUND_FACE_MIN_DOWN {
+                continue;
+            }
+            let Some(hit) = ground_hits.iter().next() else { continue };
+            let normal = hit.normal1.normalize_or_zero();
+            new_ground = GroundState {
+                face: Some(face),
+                normal,
+                slope_angle: normal.angle_between(Vec3::Y),
+                entity: Some(hit.entity),
+            };
+        }
+        if new_ground.grounded() && !ground.grounded() {
+            landed.send(PlayerLanded { player, face: new_ground.face.unwrap(), impact_speed: linear_velocity.length() });
+        }
+        *ground = new_ground;
+    }
+}
 
-        //camera_look.0.y = camera_look.0.y.clamp(-1.1, 0.40);
-        
-        //let camera_dirx = Quat::from_axis_angle(Vec3::Y, camera_look.0.x);
-        //velocity_change = camera_dirx*velocity_change;
+const WALL_CAST_DIRECTIONS: usize = 8;
 
-        linear_velocity.x += velocity_change.x;
-        linear_velocity.z += velocity_change.z;
+/// Casts the coin a short way in every horizontal direction and keeps the
+/// closest near-vertical surface it finds.
+fn update_wall_state(
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+    mut players: Query<(Entity, &Position, &Rotation, &Collider, &mut WallState), With<Player>>,
+) {
+    for (entity, position, rotation, collider, mut wall) in players.iter_mut() {
+        wall.cooldown = (wall.cooldown - time.delta_seconds()).max(0.0);
+        let mut closest: Option<(f32, Vec3, Entity)> = None;
+        for i in 0..WALL_CAST_DIRECTIONS {
+            let angle = 2.0 * PI * i as f32 / WALL_CAST_DIRECTIONS as f32;
+            let direction = Vec3::new(angle.cos(), 0.0, angle.sin());
+            let Some(hit) = spatial_query.cast_shape(
+                collider,
+                position.0,
+                rotation.0,
+                direction,
+                WALL_CAST_DISTANCE,
+                true,
+                solid_filter().without_entities([entity]),
+            ) else {
+                continue;
+            };
+            let normal = hit.normal1.normalize_or_zero();
+            let is_wall = normal.y.abs() < 0.3;
+            if is_wall && closest.map_or(true, |(toi, _, _)| hit.time_of_impact < toi) {
+                closest = Some((hit.time_of_impact, normal, hit.entity));
+            }
+        }
+        wall.normal = closest.map(|(_, normal, _)| normal);
+        wall.entity = closest.map(|(_, _, entity)| entity);
+    }
+}
 
-        // Slow player down on the x and y axes
-        linear_velocity.x *= 0.8;
-        linear_velocity.z *= 0.8;
+/// Kicks off the wall along its normal. Only in the air, ground jumps are
+/// handled by `jump`. The kick takes the whole press, so the release can't
+/// also set off a coyote or buffered jump.
+fn wall_jump(
+    mut players: Query<(Entity, &CoinInput, &GroundState, &mut WallState, &mut JumpTimers, &mut JumpStrength, &mut LinearVelocity), With<Player>>,
+    mut jumped: EventWriter<PlayerJumped>,
+) {
+    for (player, input, ground, mut wall, mut timers, mut jump_strength, mut linear_velocity) in players.iter_mut() {
+        if !input.jump_pressed {
+            continue;
+        }
+        let Some(normal) = wall.normal else { continue };
+        if ground.grounded() || wall.cooldown > 0.0 {
+            continue;
+        }
+        // keep the speed along the wall, replace the rest with the kick
+        let along_wall = linear_velocity.0.reject_from(normal);
+        linear_velocity.0 = Vec3::new(along_wall.x, 0.0, along_wall.z) + normal * WALL_JUMP_PUSH + Vec3::Y * WALL_JUMP_UP;
+        wall.cooldown = WALL_JUMP_COOLDOWN;
+        timers.since_grounded = f32::INFINITY;
+        timers.buffered = 0.0;
+        timers.release_spent = true;
+        jump_strength.0 = 0.0;
+        jumped.send(PlayerJumped { player, strength: WALL_JUMP_UP });
     }
 }
 
+/// Friction against the wall slows the fall, which gives time to chain the
+/// next wall jump.
+fn wall_slide(
+    mut players: Query<(&GroundState, &WallState, &mut LinearVelocity), With<Player>>,
+) {
+    for (ground, wall, mut linear_velocity) in players.iter_mut() {
+        if wall.normal.is_some() && !ground.grounded() && linear_velocity.y < -WALL_SLIDE_MAX_SPEED {
+            linear_velocity.y = -WALL_SLIDE_MAX_SPEED;
+        }
+    }
+}
 
-fn camera_follow(
-    player_query: Query<&GlobalTransform, With<Player>>,
-    mut camera_query: Query<&mut Transform, With<Camera>>,
+fn detect_flip(
+    mut players: Query<(Entity, &GlobalTransform, &mut FaceUp), With<Player>>,
+    mut flipped: EventWriter<PlayerFlipped>,
 ) {
+    for (player, transform, mut face_up) in players.iter_mut() {
+        let face = if transform.up().y >= 0.0 { Face::Heads } else { Face::Tails };
+        if face != face_up.0 {
+            face_up.0 = face;
+            flipped.send(PlayerFlipped { player, face });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+    use bevy::ecs::event::ManualEventReader;
+    use crate::headless::*;
+    use crate::level::{LevelPiece, LevelPlugin, PieceKind};
+
+    const WALL_TEST_SPEEDS: [f32; 6] = [20.0, 50.0, 100.0, 200.0, 400.0, 800.0];
+
+    #[test]
+    fn coin_settles_on_floor() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        sim.run_ticks(180);
+        let position = sim.player_position();
+        // lying flat on the testmap floor, not on the setup's ground plane below it
+        let resting = TESTMAP_FLOOR_TOP + 0.5 * crate::coin::COIN_HEIGHT;
+        assert!((position.y - resting).abs() < 0.15, "coin not resting on the floor: {:?}", position);
+        assert!(sim.player_velocity().length() < 0.5, "coin still moving: {:?}", sim.player_velocity());
+    }
+
+    #[test]
+    fn checkpoint_volume_is_not_ground() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().with_plugin(LevelPlugin).build();
+        let checkpoint = LevelPiece::new(PieceKind::Checkpoint, Vec3::ZERO);
+        // hanging half a unit above the floor, so only the volume is in reach
+        let bottom = TESTMAP_FLOOR_TOP + 0.5;
+        let spot = Vec3::new(10.0, bottom + 0.5 * checkpoint.size.y, -10.0);
+        sim.load_pieces(vec![LevelPiece{position: spot, ..checkpoint}]);
+        sim.place_player(spot + Vec3::Y * (0.5 * checkpoint.size.y + 1.0));
+        let mut ticks = 0;
+        while sim.player_position().y > bottom {
+            assert!(!sim.player_ground().grounded(), "the checkpoint counted as ground at {}", sim.player_position());
+            assert!(ticks < 180, "the coin got stuck in the checkpoint at {}", sim.player_position());
+            sim.step();
+            ticks += 1;
+        }
+    }
+
+    #[test]
+    fn full_charge_jump_clears_parkour_step() {
+        let charge_ticks = (MAX_JUMP_TIME_LENGTH / PHYSICS_TICK) as usize;
+        let mut sim = HeadlessAppBuilder::new()
+            .with_testmap()
+            .with_script(InputScript::new()
+                .wait(30)
+                .hold(&[KeyCode::Space], charge_ticks)
+                // let go about when it lands, or it rolls off the far side
+                .hold(&[KeyCode::S], 60)
+                .wait(60))
+            .build();
+        let step = TESTMAP_PARKOUR_STEP;
+        sim.place_player(Vec3::new(step.x, TESTMAP_FLOOR_TOP + 0.2, step.z - 6.0));
+        sim.run_script();
+        let position = sim.player_position();
+        let on_step = (position.x - step.x).abs() < 2.5
+            && (position.z - step.z).abs() < 2.5
+            && position.y > TESTMAP_PARKOUR_STEP_TOP;
+        assert!(on_step, "coin ended up at {:?}", position);
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn coin_never_tunnels_through_wall() {
+        for speed in WALL_TEST_SPEEDS {
+            for height in [TESTMAP_FLOOR_TOP + 0.2, TESTMAP_FLOOR_TOP + 2.0] {
+                let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+                sim.place_player(Vec3::new(TESTMAP_EAST_WALL_X - 5.0, height, -20.0));
+                sim.set_player_velocity(Vec3::new(speed, 0.0, 0.0));
+                sim.run_ticks(60);
+                let position = sim.player_position();
+                assert!(position.x < TESTMAP_EAST_WALL_X, "at speed {} the coin ended up behind the wall at {:?}", speed, position);
+            }
+        }
+    }
+
+    #[test]
+    fn fast_coin_keeps_speed_through_checkpoint() {
+        let speed = WALL_TEST_SPEEDS[1];
+        let mut sim = HeadlessAppBuilder::new().with_testmap().with_plugin(LevelPlugin).build();
+        let checkpoint = LevelPiece::new(PieceKind::Checkpoint, Vec3::new(10.0, TESTMAP_FLOOR_TOP + 3.0, -10.0));
+        let far_side = checkpoint.position.x + checkpoint.size.x;
+        sim.load_pieces(vec![checkpoint.clone()]);
+        sim.place_player(checkpoint.position - Vec3::X * checkpoint.size.x);
+        sim.set_player_velocity(Vec3::new(speed, 0.0, 0.0));
+        while sim.player_position().x < far_side {
+            assert!(sim.player_velocity().x > 0.9 * speed, "the checkpoint slowed the coin to {}", sim.player_velocity());
+            sim.step();
+        }
+    }
+
+    #[test]
+    fn buffered_jump_fires_on_landing() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        sim.place_player(Vec3::new(0.0, TESTMAP_FLOOR_TOP + 3.0, -10.0));
+        // tap jump while still falling
+        while sim.player_position().y > TESTMAP_FLOOR_TOP + 0.6 {
+            sim.step_with(&[]);
+        }
+        assert!(!sim.player_ground().grounded(), "coin was already grounded before the tap");
+        sim.step_with(&[KeyCode::Space]);
+        sim.step_with(&[]);
+        let mut highest = f32::MIN;
+        for _ in 0..60 {
+            sim.step_with(&[]);
+            highest = highest.max(sim.player_position().y);
+        }
+        assert!(highest >= TESTMAP_FLOOR_TOP + 1.0, "coin never jumped after landing, highest point {}", highest);
+    }
+
+    #[test]
+    fn coyote_jump_after_leaving_ledge() {
+        let step = TESTMAP_PARKOUR_STEP;
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        sim.place_player(Vec3::new(step.x, TESTMAP_PARKOUR_STEP_TOP + 0.2, step.z));
+        sim.run_ticks(30);
+        // roll towards +z until the coin leaves the block
+        let mut ticks = 0;
+        while sim.player_ground().grounded() {
+            sim.step_with(&[KeyCode::S]);
+            ticks += 1;
+            assert!(ticks <= 300, "coin never left the ledge");
+        }
+        sim.step_with(&[KeyCode::S, KeyCode::Space]);
+        sim.step_with(&[]);
+        assert!(sim.player_velocity().y > 0.0, "no jump after leaving the ledge, velocity {:?}", sim.player_velocity());
+    }
+
+    /// Steps once with `keys` and returns how many jumps that set off.
+    fn count_jumps(sim: &mut HeadlessApp, reader: &mut ManualEventReader<PlayerJumped>, keys: &[KeyCode]) -> usize {
+        sim.step_with(keys);
+        reader.iter(sim.world().resource::<Events<PlayerJumped>>()).count()
+    }
+
+    #[test]
+    fn wall_kick_release_is_not_buffered() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        let mut reader = ManualEventReader::<PlayerJumped>::default();
+        // in the air, the rim just short of the east wall
+        sim.place_player(Vec3::new(TESTMAP_EAST_WALL_X - 1.1, TESTMAP_FLOOR_TOP + 3.0, -20.0));
+        sim.step_with(&[]);
+        let mut jumps = count_jumps(&mut sim, &mut reader, &[KeyCode::Space]);
+        assert_eq!(jumps, 1, "no wall kick");
+        // hold on until just before landing, then let go
+        let mut ticks = 0;
+        while sim.player_velocity().y > 0.0 || sim.player_position().y > TESTMAP_FLOOR_TOP + 0.6 {
+            jumps += count_jumps(&mut sim, &mut reader, &[KeyCode::Space]);
+            ticks += 1;
+            assert!(ticks <= 300, "the coin never came down");
+        }
+        for _ in 0..60 {
+            jumps += count_jumps(&mut sim, &mut reader, &[]);
+        }
+        assert_eq!(jumps, 1, "the release after the wall kick jumped again");
+    }
+
+    #[test]
+    fn wall_kick_uses_up_coyote_jump() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().with_plugin(LevelPlugin).build();
+        let mut reader = ManualEventReader::<PlayerJumped>::default();
+        // a ledge with a wall flush against its -z side
+        let ledge = LevelPiece::new(PieceKind::Cuboid, Vec3::new(10.0, TESTMAP_FLOOR_TOP + 1.0, -10.0));
+        let half = 0.5 * ledge.size;
+        let wall = LevelPiece::new(PieceKind::Wall, ledge.position - Vec3::Z * (half.z + 0.1));
+        sim.load_pieces(vec![ledge.clone(), wall]);
+        sim.place_player(ledge.position + Vec3::Y * (half.y + 0.2));
+        sim.run_ticks(30);
+        // roll off towards +x along the wall
+        let mut ticks = 0;
+        while sim.player_ground().grounded() {
+            sim.step_with(&[KeyCode::D]);
+            ticks += 1;
+            assert!(ticks <= 300, "coin never left the ledge");
+        }
+        let mut jumps = count_jumps(&mut sim, &mut reader, &[KeyCode::D, KeyCode::Space]);
+        assert_eq!(jumps, 1, "no wall kick off the ledge");
+        for _ in 0..30 {
+            jumps += count_jumps(&mut sim, &mut reader, &[]);
+        }
+        assert_eq!(jumps, 1, "the release added a coyote jump to the wall kick");
+    }
+
+    #[test]
+    fn coin_runs_down_ramp() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        sim.place_player(TESTMAP_RAMP_CENTER + Vec3::Y * 0.6);
+        sim.run_ticks(120);
+        let position = sim.player_position();
+        assert!(position.z > TESTMAP_RAMP_CENTER.z + 1.0, "coin stayed on the ramp at {:?}", position);
+    }
+
+    #[test]
+    fn tab_swaps_controlled_coin() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        let first = sim.player();
+        let second = sim.spawn_coin(Vec3::new(10.0, TESTMAP_FLOOR_TOP + 0.2, -10.0));
+        sim.run_ticks(60);
+        sim.step_with(&[KeyCode::Tab]);
+        sim.step_with(&[]);
+        assert_eq!(sim.player(), second, "the second coin didn't get control");
+        let first_before = sim.position_of(first);
+        let second_before = sim.position_of(second);
+        for _ in 0..60 {
+            sim.step_with(&[KeyCode::W]);
+        }
+        let first_moved = sim.position_of(first).distance(first_before);
+        let second_moved = sim.position_of(second).distance(second_before);
+        assert!(first_moved < 0.5 && second_moved > 1.0, "first coin moved {}, second {}", first_moved, second_moved);
+    }
+
+    #[test]
+    fn seats_steer_their_own_coins() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        let first = sim.player();
+        let second = sim.spawn_coin(Vec3::new(10.0, TESTMAP_FLOOR_TOP + 0.2, -10.0));
+        sim.world().entity_mut(second).insert(Controlled{seat: 1});
+        sim.run_ticks(60);
+        for (keys, mover, still) in [([KeyCode::Up], second, first), ([KeyCode::W], first, second)] {
+            let mover_before = sim.position_of(mover);
+            let still_before = sim.position_of(still);
+            for _ in 0..60 {
+                sim.step_with(&keys);
+            }
+            let moved = sim.position_of(mover).distance(mover_before);
+            let drifted = sim.position_of(still).distance(still_before);
+            assert!(moved > 1.0 && drifted < 0.5, "{:?} moved its coin {}, the other one {}", keys, moved, drifted);
+            sim.run_ticks(60);
+        }
+    }
+
+    #[test]
+    fn forward_follows_each_seats_camera() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().build();
+        let first = sim.player();
+        let second = sim.spawn_coin(Vec3::new(10.0, TESTMAP_FLOOR_TOP + 0.2, -10.0));
+        sim.world().entity_mut(second).insert(Controlled{seat: 1});
+        // seat 0 looks down -x, seat 1 down +x
+        let world = sim.world();
+        world.query::<&mut CameraRig>().single_mut(world).yaw = FRAC_PI_2;
+        let mut rig = CameraRig::for_seat(1);
+        rig.yaw = -FRAC_PI_2;
+        world.spawn(rig);
+        sim.run_ticks(60);
+        let first_before = sim.position_of(first);
+        let second_before = sim.position_of(second);
+        for _ in 0..60 {
+            sim.step_with(&[KeyCode::W, KeyCode::Up]);
+        }
+        let first_moved = sim.position_of(first) - first_before;
+        let second_moved = sim.position_of(second) - second_before;
+        assert!(first_moved.x < -1.0 && first_moved.z.abs() < 0.5, "seat 0 went {} instead of -x", first_moved);
+        assert!(second_moved.x > 1.0 && second_moved.z.abs() < 0.5, "seat 1 went {} instead of +x", second_moved);
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::prelude::*;
+
+use crate::generator::{COURSE_PREFIX, DAILY_PREFIX};
+
+/// What the player has achieved so far. Skins and level unlocks are checked
+/// against this.
+#[derive(Resource, Default, Debug, Clone)]
+pub struct Progress {
+    /// Ids from `level::collectible_id`.
+    pub collectibles_found: BTreeSet<String>,
+    pub completed_levels: Vec<String>,
+    pub unlocked_levels: BTreeSet<String>,
+    /// Best completion time in seconds per level.
+    pub personal_bests: BTreeMap<String, f32>,
+}
+
+impl Progress {
+    pub fn has_completed(&self, level: &str) -> bool {
+        self.completed_levels.iter().any(|l| l == level)
+    }
+
+    /// Marks `level` done and keeps `time` if it beats the best so far.
+    pub fn record_completion(&mut self, level: &str, time: f32) {
+        if !self.has_completed(level) {
+            self.completed_levels.push(level.to_string());
+        }
+        self.unlocked_levels.insert(level.to_string());
+        let best = self.personal_bests.entry(level.to_string()).or_insert(time);
+        *best = best.min(time);
+    }
+
+    /// Generated courses finished, daily ones included.
+    pub fn courses_completed(&self) -> usize {
+        self.completed_levels.iter()
+            .filter(|l| l.starts_with(COURSE_PREFIX) || l.starts_with(DAILY_PREFIX))
+            .count()
+    }
+}
@@ -0,0 +1,239 @@
+//! Loose physics props: crates, balls and coins nobody plays. Levels place
+//! them with a mass and a material, the coin pushes them around, they stack,
+//! and they're what weighs down buttons that a coin alone is too light for
+//! (`PressurePlate::min_mass`).
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct PropsPlugin;
+
+impl Plugin for PropsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, return_fallen_props)
+            ;
+    }
+}
+
+// a prop below this fell out of the level and goes back to where it started
+const PROP_FALL_DEPTH: f32 = -50.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PropShape {
+    Crate,
+    Ball,
+    /// A coin like the player, just nobody's.
+    Coin,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PropMaterial {
+    Wood,
+    Metal,
+    Rubber,
+    Ice,
+}
+
+impl PropMaterial {
+    pub fn friction(self) -> f32 {
+        match self {
+            PropMaterial::Wood => 0.6,
+            PropMaterial::Metal => 0.4,
+            PropMaterial::Rubber => 0.9,
+            PropMaterial::Ice => 0.02,
+        }
+    }
+
+    pub fn restitution(self) -> f32 {
+        match self {
+            PropMaterial::Wood => 0.1,
+            PropMaterial::Metal => 0.05,
+            PropMaterial::Rubber => 0.7,
+            PropMaterial::Ice => 0.0,
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            PropMaterial::Wood => Color::rgb(0.55, 0.35, 0.15),
+            PropMaterial::Metal => Color::rgb(0.6, 0.6, 0.65),
+            PropMaterial::Rubber => Color::rgb(0.8, 0.2, 0.2),
+            PropMaterial::Ice => Color::rgba(0.7, 0.9, 1.0, 0.7),
+        }
+    }
+}
+
+/// What a prop piece in a level file is. It fills the piece's size: a crate
+/// is the box itself, a ball and a coin take their diameter from the x size.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct PropSpec {
+    pub shape: PropShape,
+    pub material: PropMaterial,
+    /// In kg. For scale, the player's coin weighs about 0.6.
+    pub mass: f32,
+}
+
+impl Default for PropSpec {
+    fn default() -> Self {
+        Self{shape: PropShape::Crate, material: PropMaterial::Wood, mass: 2.0}
+    }
+}
+
+impl PropSpec {
+    pub fn collider(&self, size: Vec3) -> Collider {
+        match self.shape {
+            PropShape::Crate => Collider::cuboid(size.x, size.y, size.z),
+            PropShape::Ball => Collider::ball(0.5 * size.x),
+            PropShape::Coin => Collider::cylinder(size.y, 0.5 * size.x),
+        }
+    }
+
+    pub fn mesh(&self, size: Vec3) -> Mesh {
+        match self.shape {
+            PropShape::Crate => shape::Box::new(size.x, size.y, size.z).into(),
+            PropShape::Ball => shape::UVSphere{radius: 0.5 * size.x, sectors: 24, stacks: 16}.into(),
+            PropShape::Coin => shape::Cylinder{radius: 0.5 * size.x, height: size.y, resolution: 32, segments: 1}.into(),
+        }
+    }
+
+    fn volume(&self, size: Vec3) -> f32 {
+        let radius = 0.5 * size.x;
+        match self.shape {
+            PropShape::Crate => size.x * size.y * size.z,
+            PropShape::Ball => 4.0 / 3.0 * PI * radius.powi(3),
+            PropShape::Coin => PI * radius * radius * size.y,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Prop {
+    pub spec: PropSpec,
+    /// Where it started, and where it goes back to if it falls out of the level.
+    pub home: Transform,
+}
+
+pub fn spawn_prop(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    spec: PropSpec,
+    transform: Transform,
+    size: Vec3,
+) -> Entity {
+    let collider = spec.collider(size);
+    let density = spec.mass / spec.volume(size).max(1e-3);
+    let color = spec.material.color();
+    commands.spawn((
+        Prop{spec, home: transform},
+        PbrBundle {
+            mesh: meshes.add(spec.mesh(size)),
+            material: materials.add(StandardMaterial {
+                base_color: color,
+                alpha_mode: if color.a() < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+                ..default()
+            }),
+            transform,
+            ..default()
+        },
+        RigidBody::Dynamic,
+        Position(transform.translation),
+        Rotation(transform.rotation),
+        // on the collider, a separate bundle would get the collider's own
+        // default density added on top
+        ColliderMassProperties::new_computed(&collider, density),
+        collider,
+        Friction::new(spec.material.friction()),
+        Restitution::new(spec.material.restitution()),
+    )).id()
+}
+
+fn return_fallen_props(
+    mut props: Query<(&Prop, &mut Position, &mut Rotation, &mut LinearVelocity, &mut AngularVelocity, &mut Transform)>,
+) {
+    for (prop, mut position, mut rotation, mut linear_velocity, mut angular_velocity, mut transform) in props.iter_mut() {
+        if position.0.y > PROP_FALL_DEPTH {
+            continue;
+        }
+        position.0 = prop.home.translation;
+        rotation.0 = prop.home.rotation;
+        linear_velocity.0 = Vec3::ZERO;
+        angular_velocity.0 = Vec3::ZERO;
+        *transform = prop.home;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::*;
+    use crate::level::{LevelPiece, LevelPlugin, PieceKind, Wiring, LEVEL_SIGNAL_BASE};
+    use crate::level_logic::Signals;
+
+    const CRATE_SPOT: Vec3 = Vec3::new(10.0, TESTMAP_FLOOR_TOP, -10.0);
+
+    #[test]
+    fn coin_pushes_crate() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().with_plugin(LevelPlugin).build();
+        sim.load_pieces(vec![LevelPiece::new(PieceKind::Prop, CRATE_SPOT + Vec3::Y * 0.5)]);
+        sim.place_player(CRATE_SPOT + Vec3::new(0.0, 0.2, 3.0));
+        sim.run_ticks(60);
+        let before = sim.prop_position().expect("the crate didn't spawn");
+        for _ in 0..90 {
+            sim.step_with(&[KeyCode::W]);
+        }
+        let after = sim.prop_position().expect("the crate is gone");
+        assert!(after.z < before.z - 0.5, "the crate only went from {} to {}", before, after);
+    }
+
+    #[test]
+    fn weighted_button_needs_crate() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().with_plugin(LevelPlugin).build();
+        let button = |signal, position: Vec3| LevelPiece{
+            wiring: Some(Wiring{signal, min_mass: 1.5}),
+            ..LevelPiece::new(PieceKind::Button, position + Vec3::Y * 0.1)
+        };
+        let coin_spot = Vec3::new(0.0, TESTMAP_FLOOR_TOP, 0.0);
+        sim.load_pieces(vec![
+            button(1, CRATE_SPOT),
+            // set down in place, a dropped crate skids off on xpbd's single contact point
+            LevelPiece::new(PieceKind::Prop, CRATE_SPOT + Vec3::Y * 0.5),
+            button(2, coin_spot),
+        ]);
+        sim.place_player(coin_spot + Vec3::Y * 0.5);
+        sim.run_ticks(120);
+        let signals = sim.world().resource::<Signals>();
+        assert!(signals.get(LEVEL_SIGNAL_BASE + 1), "the crate didn't press its button");
+        assert!(!signals.get(LEVEL_SIGNAL_BASE + 2), "the coin alone pressed its button");
+    }
+
+    #[test]
+    fn stacked_props_add_up_on_button() {
+        let mut sim = HeadlessAppBuilder::new().with_testmap().with_plugin(LevelPlugin).build();
+        let button = |signal, position: Vec3| LevelPiece{
+            wiring: Some(Wiring{signal, min_mass: 1.5}),
+            ..LevelPiece::new(PieceKind::Button, position + Vec3::Y * 0.1)
+        };
+        // each too light for the button on its own
+        let light_crate = |position: Vec3| LevelPiece{
+            prop: Some(PropSpec{mass: 1.0, ..default()}),
+            ..LevelPiece::new(PieceKind::Prop, position)
+        };
+        let lone_spot = CRATE_SPOT + Vec3::X * 4.0;
+        sim.load_pieces(vec![
+            button(1, CRATE_SPOT),
+            light_crate(CRATE_SPOT + Vec3::Y * 0.5),
+            light_crate(CRATE_SPOT + Vec3::Y * 1.5),
+            button(2, lone_spot),
+            light_crate(lone_spot + Vec3::Y * 0.5),
+        ]);
+        sim.run_ticks(120);
+        let signals = sim.world().resource::<Signals>();
+        assert!(signals.get(LEVEL_SIGNAL_BASE + 1), "the stacked crates didn't press their button");
+        assert!(!signals.get(LEVEL_SIGNAL_BASE + 2), "a single light crate pressed its button");
+    }
+}
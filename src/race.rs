@@ -0,0 +1,339 @@
+//! Local two player races on a generated course. The screen is split down
+//! the middle, seat 0 plays on the left with the keyboard's main bindings or
+//! the first gamepad, seat 1 on the right with the arrow keys or the second
+//! gamepad. A single gamepad goes to seat 1. After a countdown both coins run for the goal, the race ends once
+//! both made it or a while after the first one did.
+
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::Viewport;
+use bevy::window::PrimaryWindow;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::AppState;
+use crate::camera::{CameraMode, CameraRig};
+use crate::generator::generate_course;
+use crate::level::{CurrentLevel, LastCheckpoint, LevelCompleted};
+use crate::menu::{menu_button, menu_button_colors, text_style};
+use crate::player::{spawn_coin, Controlled, ControlsLocked, JumpEnvelope, Player};
+
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<StartRace>()
+            .add_systems(Update, start_race)
+            .add_systems(Update, split_viewports.run_if(resource_exists::<Race>()))
+            .add_systems(Update, (
+                race_clock,
+                race_hud,
+            ).chain().run_if(resource_exists::<Race>().and_then(in_state(AppState::InGame))))
+            .add_systems(Update, (
+                results_actions,
+                menu_button_colors,
+            ).run_if(resource_exists::<Race>().and_then(race_over)))
+            .add_systems(OnEnter(AppState::Menu), end_race)
+            ;
+    }
+}
+
+pub const RACERS: usize = 2;
+const COUNTDOWN: f32 = 3.0;
+// how long the others get once the first coin is through
+const FINISH_GRACE: f32 = 30.0;
+// "GO!" stays up this long after the countdown
+const GO_TIME: f32 = 1.0;
+// the coins start side by side, this far left and right of the spawn
+const START_OFFSET: f32 = 1.5;
+const HUD_COLORS: [Color; RACERS] = [Color::rgb(1.0, 0.85, 0.3), Color::rgb(0.4, 0.8, 1.0)];
+
+/// Builds the course for `seed` and (re)starts a race on it.
+#[derive(Event)]
+pub struct StartRace {
+    pub seed: u64,
+}
+
+#[derive(Resource, Debug)]
+pub struct Race {
+    pub seed: u64,
+    /// Seconds left before the start, the coins are locked until then.
+    pub countdown: f32,
+    /// Seconds since the start.
+    pub clock: f32,
+    /// Finish time per seat, `None` while running and for a DNF.
+    pub finishes: [Option<f32>; RACERS],
+    pub over: bool,
+}
+
+impl Race {
+    fn new(seed: u64) -> Self {
+        Self{seed, countdown: COUNTDOWN, clock: 0.0, finishes: [None; RACERS], over: false}
+    }
+
+    fn first_finish(&self) -> Option<f32> {
+        self.finishes.iter().flatten().copied().reduce(f32::min)
+    }
+
+    /// 1 for the winner. Ties share a place.
+    fn placement(&self, seat: usize) -> Option<usize> {
+        let time = self.finishes[seat]?;
+        Some(1 + self.finishes.iter().flatten().filter(|other| **other < time).count())
+    }
+}
+
+/// Everything the race spawns on top of the normal game, gone with the race.
+#[derive(Component)]
+struct RaceEntity;
+
+#[derive(Component)]
+struct RaceHud(usize);
+
+#[derive(Component)]
+enum RaceButton {
+    Rematch,
+    MainMenu,
+}
+
+fn race_over(race: Res<Race>) -> bool {
+    race.over
+}
+
+fn ordinal(place: usize) -> &'static str {
+    if place == 1 { "1st" } else { "2nd" }
+}
+
+fn start_race(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut requests: EventReader<StartRace>,
+    mut current: ResMut<CurrentLevel>,
+    mut locked: ResMut<ControlsLocked>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    leftovers: Query<Entity, With<RaceEntity>>,
+    mut players: Query<(&Controlled, &mut Position, &mut Transform, &mut LinearVelocity, &mut AngularVelocity, &mut LastCheckpoint), With<Player>>,
+    mut cameras: Query<(Entity, &Transform, &mut CameraRig), (Without<Player>, Without<RaceEntity>)>,
+) {
+    let Some(StartRace{seed}) = requests.iter().last() else { return };
+    let level = generate_course(*seed, &JumpEnvelope::default());
+    let start = |seat: usize| level.spawn + Vec3::X * START_OFFSET * if seat == 0 { -1.0 } else { 1.0 };
+
+    // a rematch starts from scratch too
+    for entity in leftovers.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (controlled, mut position, mut transform, mut linear_velocity, mut angular_velocity, mut checkpoint) in players.iter_mut() {
+        if controlled.seat != 0 {
+            continue;
+        }
+        position.0 = start(0);
+        transform.translation = start(0);
+        transform.rotation = Quat::IDENTITY;
+        linear_velocity.0 = Vec3::ZERO;
+        angular_velocity.0 = Vec3::ZERO;
+        checkpoint.0 = None;
+    }
+    let second = spawn_coin(&mut commands, start(1));
+    commands.entity(second).insert((Controlled{seat: 1}, RaceEntity));
+
+    for (entity, transform, mut rig) in cameras.iter_mut() {
+        rig.set_mode(CameraMode::Follow, *transform);
+        // the UI gets its own camera across the whole window
+        commands.entity(entity).insert(UiCameraConfig{show_ui: false});
+    }
+    commands.spawn((RaceEntity, UiCameraConfig{show_ui: false}, CameraRig::for_seat(1), Camera3dBundle {
+        camera: Camera{order: 1, ..default()},
+        camera_3d: Camera3d{clear_color: ClearColorConfig::None, ..default()},
+        transform: Transform::from_translation(start(1)),
+        ..default()
+    }));
+    commands.spawn((RaceEntity, Camera2dBundle {
+        camera: Camera{order: 2, ..default()},
+        camera_2d: Camera2d{clear_color: ClearColorConfig::None},
+        ..default()
+    }));
+
+    for seat in 0..RACERS {
+        let mut style = text_style(&asset_server, 28.0);
+        style.color = HUD_COLORS[seat];
+        commands.spawn((RaceEntity, RaceHud(seat), TextBundle::from_section("", style).with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(50.0 * seat as f32 + 2.0),
+            top: Val::Px(10.0),
+            ..default()
+        })));
+    }
+
+    current.0 = Some(level);
+    locked.0 = true;
+    commands.insert_resource(Race::new(*seed));
+    if let Ok(mut window) = windows.get_single_mut() {
+        window.cursor.visible = false;
+    }
+    next_state.set(AppState::InGame);
+}
+
+/// Left half for seat 0, right half for seat 1, kept in step with the window.
+fn split_viewports(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&mut Camera, &CameraRig)>,
+) {
+    let Ok(window) = windows.get_single() else { return };
+    let size = UVec2::new(window.physical_width() / RACERS as u32, window.physical_height());
+    if size.x == 0 || size.y == 0 {
+        // minimized
+        return;
+    }
+    for (mut camera, rig) in cameras.iter_mut() {
+        let position = UVec2::new(size.x * rig.seat as u32, 0);
+        let current = camera.viewport.as_ref().map(|viewport| (viewport.physical_position, viewport.physical_size));
+        if current != Some((position, size)) {
+            camera.viewport = Some(Viewport{physical_position: position, physical_size: size, ..default()});
+        }
+    }
+}
+
+fn race_clock(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+    mut race: ResMut<Race>,
+    mut locked: ResMut<ControlsLocked>,
+    mut completed: EventReader<LevelCompleted>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    players: Query<&Controlled>,
+) {
+    let dt = time.delta_seconds();
+    if race.over {
+        completed.clear();
+        return;
+    }
+    if race.countdown > 0.0 {
+        race.countdown -= dt;
+        if race.countdown <= 0.0 {
+            locked.0 = false;
+        }
+        completed.clear();
+        return;
+    }
+
+    race.clock += dt;
+    for LevelCompleted{player, ..} in completed.iter() {
+        let Ok(controlled) = players.get(*player) else { continue };
+        if controlled.seat < RACERS && race.finishes[controlled.seat].is_none() {
+            let clock = race.clock;
+            race.finishes[controlled.seat] = Some(clock);
+        }
+    }
+
+    let everyone_done = race.finishes.iter().all(Option::is_some);
+    let out_of_time = race.first_finish().map_or(false, |first| race.clock > first + FINISH_GRACE);
+    if everyone_done || out_of_time {
+        race.over = true;
+        locked.0 = true;
+        spawn_results(&mut commands, &asset_server, &race);
+        if let Ok(mut window) = windows.get_single_mut() {
+            window.cursor.visible = true;
+        }
+    }
+}
+
+fn race_hud(
+    race: Res<Race>,
+    players: Query<(&Controlled, &LinearVelocity)>,
+    mut huds: Query<(&mut Text, &RaceHud)>,
+) {
+    for (mut text, RaceHud(seat)) in huds.iter_mut() {
+        let speed = players.iter()
+            .find(|(controlled, _)| controlled.seat == *seat)
+            .map_or(0.0, |(_, velocity)| velocity.length());
+        let status = if race.countdown > 0.0 {
+            format!("{}", race.countdown.ceil() as u32)
+        } else if let Some(place) = race.placement(*seat) {
+            format!("Finished {}", ordinal(place))
+        } else if race.over {
+            "DNF".to_string()
+        } else if race.clock < GO_TIME {
+            "GO!".to_string()
+        } else if let Some(first) = race.first_finish() {
+            format!("{:.0} s left", (first + FINISH_GRACE - race.clock).max(0.0))
+        } else {
+            String::new()
+        };
+        let clock = race.finishes[*seat].unwrap_or(race.clock);
+        text.sections[0].value = format!("P{}  {:.2} s\n{:.1} m/s\n{}", seat + 1, clock, speed, status);
+    }
+}
+
+fn spawn_results(commands: &mut Commands, asset_server: &AssetServer, race: &Race) {
+    let mut seats: Vec<usize> = (0..RACERS).collect();
+    seats.sort_by(|a, b| {
+        let time = |seat: &usize| race.finishes[*seat].unwrap_or(f32::INFINITY);
+        time(a).total_cmp(&time(b))
+    });
+    commands.spawn((RaceEntity, NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+        ..default()
+    })).with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Results", text_style(asset_server, 60.0)));
+        for seat in seats {
+            let line = match (race.placement(seat), race.finishes[seat]) {
+                (Some(place), Some(time)) => format!("{}  P{}  {:.2} s", ordinal(place), seat + 1, time),
+                _ => format!("DNF  P{}", seat + 1),
+            };
+            let mut style = text_style(asset_server, 32.0);
+            style.color = HUD_COLORS[seat];
+            parent.spawn(TextBundle::from_section(line, style));
+        }
+        menu_button(parent, asset_server, RaceButton::Rematch, "Rematch");
+        menu_button(parent, asset_server, RaceButton::MainMenu, "Main menu");
+    });
+}
+
+fn results_actions(
+    race: Res<Race>,
+    interaction_query: Query<(&Interaction, &RaceButton), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut rematch: EventWriter<StartRace>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            RaceButton::Rematch => rematch.send(StartRace{seed: race.seed}),
+            RaceButton::MainMenu => next_state.set(AppState::Menu),
+        }
+    }
+}
+
+/// Back to one coin on the whole screen.
+fn end_race(
+    mut commands: Commands,
+    race: Option<Res<Race>>,
+    mut locked: ResMut<ControlsLocked>,
+    leftovers: Query<Entity, With<RaceEntity>>,
+    mut cameras: Query<(Entity, &mut Camera), (With<CameraRig>, Without<RaceEntity>)>,
+) {
+    if race.is_none() {
+        return;
+    }
+    for entity in leftovers.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (entity, mut camera) in cameras.iter_mut() {
+        camera.viewport = None;
+        commands.entity(entity).remove::<UiCameraConfig>();
+    }
+    locked.0 = false;
+    commands.remove_resource::<Race>();
+}
@@ -0,0 +1,343 @@
+//! Offline check of a level file against the coin's jump envelope. Every
+//! standable top face is a node, and one face leads to another if a full
+//! speed, fully charged jump covers the distance between their closest
+//! points. Platforms, checkpoints, goals and collectibles that can't be
+//! reached from the spawn point get reported.
+//!
+//! Solid pieces that almost touch are flagged as well. A gap under two coin
+//! radii is rarely a jump on purpose, and the coin slips through on its edge
+//! once it's wider than the coin is thick.
+//!
+//! `main --check-level <name>` runs it on `assets/levels/<name>.ron`.
+
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+
+use crate::coin::{COIN_HEIGHT, COIN_RADIUS};
+use crate::game_const::*;
+use crate::level::{load_level_file, LevelFile, LevelPiece, PieceKind};
+use crate::player::JumpEnvelope;
+
+// closer than this counts as touching
+const GAP_EPSILON: f32 = 1e-3;
+const MAX_SUSPICIOUS_GAP: f32 = 2.0 * COIN_RADIUS;
+// rotations within this many degrees of a right angle keep a piece box shaped for the gap check
+const RIGHT_ANGLE_TOLERANCE: f32 = 0.01;
+
+/// A standable top face, as a quad going around its edge.
+#[derive(Clone, Copy, Debug)]
+struct Surface {
+    piece: usize,
+    corners: [Vec3; 4],
+}
+
+impl Surface {
+    fn footprint(&self) -> [Vec2; 4] {
+        self.corners.map(|corner| corner.xz())
+    }
+
+    fn height_at(&self, point: Vec2) -> f32 {
+        let [c0, c1, _, c3] = self.corners;
+        let normal = (c1 - c0).cross(c3 - c0);
+        if normal.y.abs() < f32::EPSILON {
+            return c0.y;
+        }
+        c0.y - (normal.x * (point.x - c0.x) + normal.z * (point.y - c0.z)) / normal.y
+    }
+}
+
+/// The most upward facing face of a piece, if the coin can stand on it.
+fn top_face(index: usize, piece: &LevelPiece) -> Option<Surface> {
+    let rotation = piece.rotation_quat();
+    let half = 0.5 * piece.size;
+    let axes = [(Vec3::X, half.x), (Vec3::Y, half.y), (Vec3::Z, half.z)];
+    let (axis, sign) = (0..3)
+        .flat_map(|axis| [(axis, 1.0), (axis, -1.0)])
+        .max_by(|a, b| {
+            let up_a = (rotation * (axes[a.0].0 * a.1)).y;
+            let up_b = (rotation * (axes[b.0].0 * b.1)).y;
+            up_a.total_cmp(&up_b)
+        })?;
+    let normal = rotation * (axes[axis].0 * sign);
+    if normal.angle_between(Vec3::Y) > MAX_WALKABLE_SLOPE {
+        return None;
+    }
+    let center = piece.position + normal * axes[axis].1;
+    let (u, u_half) = axes[(axis + 1) % 3];
+    let (v, v_half) = axes[(axis + 2) % 3];
+    let u = rotation * u * u_half;
+    let v = rotation * v * v_half;
+    Some(Surface{
+        piece: index,
+        corners: [center + u + v, center + u - v, center - u - v, center - u + v],
+    })
+}
+
+/// Where the coin has to get to for a sensor to trigger, the underside of
+/// its bounding box lowered by the coin's radius.
+fn target_face(index: usize, piece: &LevelPiece) -> Surface {
+    let (min, max) = bounds(piece);
+    let y = min.y - COIN_RADIUS;
+    Surface{
+        piece: index,
+        corners: [
+            Vec3::new(min.x, y, min.z),
+            Vec3::new(max.x, y, min.z),
+            Vec3::new(max.x, y, max.z),
+            Vec3::new(min.x, y, max.z),
+        ],
+    }
+}
+
+fn bounds(piece: &LevelPiece) -> (Vec3, Vec3) {
+    let rotation = Mat3::from_quat(piece.rotation_quat());
+    let half = 0.5 * piece.size;
+    let extent = Vec3::new(
+        rotation.row(0).abs().dot(half),
+        rotation.row(1).abs().dot(half),
+        rotation.row(2).abs().dot(half),
+    );
+    (piece.position - extent, piece.position + extent)
+}
+
+fn is_box_aligned(piece: &LevelPiece) -> bool {
+    piece.rotation.to_array().iter().all(|angle| {
+        let off = angle.rem_euclid(90.0);
+        off < RIGHT_ANGLE_TOLERANCE || 90.0 - off < RIGHT_ANGLE_TOLERANCE
+    })
+}
+
+fn closest_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let t = ((point - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+fn segment_intersection(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> Option<Vec2> {
+    let r = b - a;
+    let s = d - c;
+    let denominator = r.perp_dot(s);
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+    let t = (c - a).perp_dot(s) / denominator;
+    let u = (c - a).perp_dot(r) / denominator;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then(|| a + r * t)
+}
+
+fn inside(point: Vec2, polygon: &[Vec2; 4]) -> bool {
+    let sides: Vec<f32> = (0..4).map(|i| (polygon[(i + 1) % 4] - polygon[i]).perp_dot(point - polygon[i])).collect();
+    sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+}
+
+/// The closest pair of points between two convex quads, the same point twice
+/// if they overlap.
+fn closest_points(a: &[Vec2; 4], b: &[Vec2; 4]) -> (Vec2, Vec2) {
+    if let Some(point) = a.iter().find(|point| inside(**point, b)) {
+        return (*point, *point);
+    }
+    if let Some(point) = b.iter().find(|point| inside(**point, a)) {
+        return (*point, *point);
+    }
+    let mut best = (a[0], b[0]);
+    for i in 0..4 {
+        let (a0, a1) = (a[i], a[(i + 1) % 4]);
+        for j in 0..4 {
+            let (b0, b1) = (b[j], b[(j + 1) % 4]);
+            if let Some(point) = segment_intersection(a0, a1, b0, b1) {
+                return (point, point);
+            }
+            for pair in [
+                (a0, closest_on_segment(a0, b0, b1)),
+                (a1, closest_on_segment(a1, b0, b1)),
+                (closest_on_segment(b0, a0, a1), b0),
+                (closest_on_segment(b1, a0, a1), b1),
+            ] {
+                if pair.0.distance(pair.1) < best.0.distance(best.1) {
+                    best = pair;
+                }
+            }
+        }
+    }
+    best
+}
+
+fn can_jump(envelope: &JumpEnvelope, from: &Surface, to: &Surface) -> bool {
+    let (take_off, landing) = closest_points(&from.footprint(), &to.footprint());
+    let rise = to.height_at(landing) - from.height_at(take_off);
+    envelope.max_distance(rise).map_or(false, |reach| take_off.distance(landing) <= reach)
+}
+
+/// Two solid pieces with a sliver of space between them.
+#[derive(Clone, Copy, Debug)]
+pub struct Gap {
+    pub pieces: (usize, usize),
+    pub width: f32,
+    pub axis: usize,
+    pub at: Vec3,
+}
+
+#[derive(Default, Debug)]
+pub struct ReachReport {
+    pub name: String,
+    /// The piece the coin lands on from the spawn point.
+    pub spawn_on: Option<usize>,
+    pub unreachable: Vec<(usize, PieceKind, Vec3)>,
+    pub gaps: Vec<Gap>,
+}
+
+impl ReachReport {
+    pub fn is_clean(&self) -> bool {
+        self.spawn_on.is_some() && self.unreachable.is_empty() && self.gaps.is_empty()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("level {}", self.name)];
+        match self.spawn_on {
+            Some(index) => lines.push(format!("spawn lands on piece {}", index)),
+            None => lines.push("spawn is not above anything the coin can stand on".to_string()),
+        }
+        for (index, kind, position) in self.unreachable.iter() {
+            lines.push(format!("unreachable: piece {} ({}) at {:.2}", index, kind.name(), position));
+        }
+        for gap in self.gaps.iter() {
+            let risk = if gap.width >= COIN_HEIGHT { "the coin fits through on its edge" } else { "a crack the coin can snag on" };
+            lines.push(format!(
+                "gap: pieces {} and {} are {:.3} apart along {} at {:.2}, {}",
+                gap.pieces.0, gap.pieces.1, gap.width, ["x", "y", "z"][gap.axis], gap.at, risk,
+            ));
+        }
+        if self.is_clean() {
+            lines.push("everything is reachable and there are no gaps".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn check_level(level: &LevelFile, envelope: &JumpEnvelope) -> ReachReport {
+    let mut report = ReachReport{name: level.name.clone(), ..default()};
+
+    let surfaces: Vec<Surface> = level.pieces.iter().enumerate()
+        .filter(|(_, piece)| piece.kind.is_solid())
+        .filter_map(|(index, piece)| top_face(index, piece))
+        .collect();
+    let targets: Vec<Surface> = level.pieces.iter().enumerate()
+        .filter(|(_, piece)| matches!(piece.kind, PieceKind::Checkpoint | PieceKind::Goal | PieceKind::Collectible))
+        .map(|(index, piece)| target_face(index, piece))
+        .collect();
+
+    // the highest face under the spawn point is where the coin drops onto
+    let spawn = level.spawn.xz();
+    let start = surfaces.iter().enumerate()
+        .filter(|(_, surface)| inside(spawn, &surface.footprint()))
+        .filter(|(_, surface)| surface.height_at(spawn) <= level.spawn.y + COIN_RADIUS)
+        .max_by(|(_, a), (_, b)| a.height_at(spawn).total_cmp(&b.height_at(spawn)))
+        .map(|(i, _)| i);
+    report.spawn_on = start.map(|i| surfaces[i].piece);
+
+    let mut reached = vec![false; surfaces.len()];
+    let mut open: Vec<usize> = start.into_iter().collect();
+    for &i in open.iter() {
+        reached[i] = true;
+    }
+    while let Some(from) = open.pop() {
+        for to in 0..surfaces.len() {
+            if !reached[to] && can_jump(envelope, &surfaces[from], &surfaces[to]) {
+                reached[to] = true;
+                open.push(to);
+            }
+        }
+    }
+
+    for (surface, reached) in surfaces.iter().zip(reached.iter()) {
+        let piece = &level.pieces[surface.piece];
+        // the top of a wall is a ledge, not a platform anyone has to get to
+        if !reached && piece.kind != PieceKind::Wall {
+            report.unreachable.push((surface.piece, piece.kind, piece.position));
+        }
+    }
+    for target in targets.iter() {
+        let hit = surfaces.iter().zip(reached.iter())
+            .any(|(surface, reached)| *reached && can_jump(envelope, surface, target));
+        if !hit {
+            let piece = &level.pieces[target.piece];
+            report.unreachable.push((target.piece, piece.kind, piece.position));
+        }
+    }
+
+    report.gaps = find_gaps(level);
+    report
+}
+
+fn find_gaps(level: &LevelFile) -> Vec<Gap> {
+    // tilted pieces aren't boxes in world space, their bounds would give false gaps
+    let boxes: Vec<(usize, Vec3, Vec3)> = level.pieces.iter().enumerate()
+        .filter(|(_, piece)| piece.kind.is_solid() && is_box_aligned(piece))
+        .map(|(index, piece)| {
+            let (min, max) = bounds(piece);
+            (index, min, max)
+        })
+        .collect();
+
+    let mut gaps = Vec::new();
+    for (i, (a, a_min, a_max)) in boxes.iter().enumerate() {
+        for (b, b_min, b_max) in boxes[i + 1..].iter() {
+            let separation = (*a_min - *b_max).max(*b_min - *a_max);
+            let apart: Vec<usize> = (0..3).filter(|axis| separation[*axis] > -GAP_EPSILON).collect();
+            // facing each other across exactly one axis
+            let [axis] = apart[..] else { continue };
+            let width = separation[axis];
+            if width > GAP_EPSILON && width < MAX_SUSPICIOUS_GAP {
+                let at = 0.5 * (a_min.max(*b_min) + a_max.min(*b_max));
+                gaps.push(Gap{pieces: (*a, *b), width, axis, at});
+            }
+        }
+    }
+    gaps
+}
+
+/// Loads `assets/levels/<name>.ron`, checks it and prints the report. Returns
+/// false if anything was found.
+pub fn run_check(name: &str) -> bool {
+    let level = match load_level_file(name) {
+        Ok(level) => level,
+        Err(err) => {
+            eprintln!("could not load level {:?}: {:?}", name, err);
+            return false;
+        }
+    };
+    let report = check_level(&level, &JumpEnvelope::default());
+    println!("{}", report.describe());
+    report.is_clean()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_cracks_and_islands() {
+        let floor = |position| LevelPiece::new(PieceKind::Floor, position);
+        let level = LevelFile{
+            spawn: Vec3::Y,
+            pieces: vec![
+                floor(Vec3::ZERO),
+                // a hair apart from the first floor
+                floor(Vec3::X * (CUBOID_SIZE + 0.02)),
+                // way out of jumping range
+                floor(Vec3::Z * 10.0 * CUBOID_SIZE),
+                LevelPiece::new(PieceKind::Collectible, Vec3::new(0.0, 1.0, 10.0 * CUBOID_SIZE)),
+            ],
+            ..default()
+        };
+        let report = check_level(&level, &JumpEnvelope::default());
+        assert_eq!(report.spawn_on, Some(0), "{}", report.describe());
+
+        let [gap] = report.gaps[..] else { panic!("expected one gap: {}", report.describe()) };
+        assert_eq!((gap.pieces, gap.axis), ((0, 1), 0), "{}", report.describe());
+        assert!((gap.width - 0.02).abs() < 1e-3, "{}", report.describe());
+
+        let unreachable: Vec<(usize, PieceKind)> = report.unreachable.iter().map(|(index, kind, _)| (*index, *kind)).collect();
+        assert_eq!(unreachable, [(2, PieceKind::Floor), (3, PieceKind::Collectible)], "{}", report.describe());
+    }
+}
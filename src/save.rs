@@ -0,0 +1,323 @@
+//! Save profiles. Each slot is a RON file in the platform data directory.
+//!
+//! Files carry a `version`. Older versions are migrated one step at a time,
+//! newer or unreadable ones are moved aside as `*.corrupt-<unix time>` and the
+//! slot starts fresh instead of taking the game down.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::progress::Progress;
+use crate::settings::{KeyBindings, Settings};
+use crate::skins::SelectedSkin;
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ActiveProfile::load(0))
+            .add_systems(PreStartup, apply_active_profile)
+            .add_systems(Update, apply_active_profile.run_if(resource_changed::<ActiveProfile>()))
+            .add_systems(PostUpdate, store_active_profile)
+            ;
+    }
+}
+
+pub const SAVE_VERSION: u32 = 1;
+pub const SAVE_SLOTS: usize = 3;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct SaveProfile {
+    pub version: u32,
+    pub unlocked_levels: BTreeSet<String>,
+    pub completed_levels: BTreeSet<String>,
+    /// Best completion time in seconds per level.
+    pub personal_bests: BTreeMap<String, f32>,
+    pub collectibles_found: BTreeSet<String>,
+    pub skin: String,
+    pub settings: Settings,
+    pub bindings: KeyBindings,
+}
+
+impl Default for SaveProfile {
+    fn default() -> Self {
+        Self{
+            version: SAVE_VERSION,
+            unlocked_levels: BTreeSet::from(["testmap".to_string()]),
+            completed_levels: BTreeSet::new(),
+            personal_bests: BTreeMap::new(),
+            collectibles_found: BTreeSet::new(),
+            skin: SelectedSkin::default().0,
+            settings: Settings::default(),
+            bindings: KeyBindings::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Parse(String),
+    TooNew(u32),
+    /// An older version with no step in `migrate` to bring it forward.
+    NoMigration(u32),
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(err: std::io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for SaveError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        SaveError::Parse(err.to_string())
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(err: ron::Error) -> Self {
+        SaveError::Parse(err.to_string())
+    }
+}
+
+/// Only the version, so we know how to read the rest.
+#[derive(Deserialize)]
+struct SaveHeader {
+    #[serde(default)]
+    version: u32,
+}
+
+pub fn save_dir() -> PathBuf {
+    directories::ProjectDirs::from("", "", "i_am_coin")
+        .map(|dirs| dirs.data_dir().join("saves"))
+        .unwrap_or_else(|| PathBuf::from("saves"))
+}
+
+pub fn slot_path(slot: usize) -> PathBuf {
+    save_dir().join(format!("slot_{}.ron", slot))
+}
+
+pub fn parse_profile(text: &str) -> Result<SaveProfile, SaveError> {
+    let header: SaveHeader = ron::from_str(text)?;
+    match header.version {
+        SAVE_VERSION => Ok(ron::from_str(text)?),
+        version if version > SAVE_VERSION => Err(SaveError::TooNew(version)),
+        version => migrate(ron::from_str(text)?, version),
+    }
+}
+
+/// Brings an old profile up to `SAVE_VERSION`. Purely additive changes are
+/// covered by `#[serde(default)]`; anything that renames or reshapes a field
+/// gets a step here that rewrites `value` from `version` to `version + 1`.
+fn migrate(value: ron::Value, from: u32) -> Result<SaveProfile, SaveError> {
+    for version in from..SAVE_VERSION {
+        match version {
+            // version 0 never shipped anything that version 1 can't read
+            0 => {}
+            _ => return Err(SaveError::NoMigration(version)),
+        }
+    }
+    let mut profile: SaveProfile = value.into_rust()?;
+    profile.version = SAVE_VERSION;
+    Ok(profile)
+}
+
+pub fn load_slot(slot: usize) -> Result<Option<SaveProfile>, SaveError> {
+    load_profile(&slot_path(slot))
+}
+
+fn load_profile(path: &Path) -> Result<Option<SaveProfile>, SaveError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)?;
+    parse_profile(&text).map(Some)
+}
+
+pub fn save_slot(slot: usize, profile: &SaveProfile) -> Result<(), SaveError> {
+    save_profile(&slot_path(slot), profile)
+}
+
+/// Writes to a temporary file next to the slot and renames it over the old one,
+/// so a crash mid-write never leaves a half written profile behind.
+fn save_profile(path: &Path, profile: &SaveProfile) -> Result<(), SaveError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text = ron::ser::to_string_pretty(profile, ron::ser::PrettyConfig::default())?;
+    let tmp_path = path.with_extension("ron.tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub fn delete_slot(slot: usize) -> Result<(), SaveError> {
+    let path = slot_path(slot);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Moves a profile that couldn't be read out of the way so it can be inspected later.
+fn back_up_corrupt_profile(path: &Path) {
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let backup = path.with_extension(format!("ron.corrupt-{}", stamp));
+    match fs::rename(path, &backup) {
+        Ok(()) => warn!("{:?} was unreadable, moved it to {:?}", path, backup),
+        Err(err) => error!("{:?} was unreadable and could not be backed up: {}", path, err),
+    }
+}
+
+/// The profile at `path`, or a fresh one if it is missing or broken.
+fn load_or_default(path: &Path) -> SaveProfile {
+    match load_profile(path) {
+        Ok(Some(profile)) => profile,
+        Ok(None) => SaveProfile::default(),
+        Err(err) => {
+            warn!("could not load {:?}: {:?}", path, err);
+            back_up_corrupt_profile(path);
+            SaveProfile::default()
+        }
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct ActiveProfile {
+    pub slot: usize,
+    pub profile: SaveProfile,
+}
+
+impl ActiveProfile {
+    /// Loads a slot, falling back to a fresh profile if it is missing or broken.
+    pub fn load(slot: usize) -> Self {
+        Self{slot, profile: load_or_default(&slot_path(slot))}
+    }
+
+    pub fn save(&self) {
+        if let Err(err) = save_slot(self.slot, &self.profile) {
+            error!("could not write save slot {}: {:?}", self.slot, err);
+        }
+    }
+}
+
+/// Copies the profile into the resources the rest of the game reads.
+fn apply_active_profile(
+    mut commands: Commands,
+    active: Res<ActiveProfile>,
+) {
+    let profile = &active.profile;
+    commands.insert_resource(Progress {
+        collectibles_found: profile.collectibles_found.clone(),
+        completed_levels: profile.completed_levels.iter().cloned().collect(),
+        unlocked_levels: profile.unlocked_levels.clone(),
+        personal_bests: profile.personal_bests.clone(),
+    });
+    commands.insert_resource(SelectedSkin(profile.skin.clone()));
+    commands.insert_resource(profile.settings.clone());
+    commands.insert_resource(profile.bindings.clone().with_missing_defaults());
+}
+
+/// Writes back to disk whenever something that lives in the profile changes.
+fn store_active_profile(
+    mut active: ResMut<ActiveProfile>,
+    progress: Res<Progress>,
+    skin: Res<SelectedSkin>,
+    settings: Res<Settings>,
+    bindings: Res<KeyBindings>,
+) {
+    if active.is_changed() {
+        return;
+    }
+    if !(progress.is_changed() || skin.is_changed() || settings.is_changed() || bindings.is_changed()) {
+        return;
+    }
+    let profile = &mut active.bypass_change_detection().profile;
+    profile.completed_levels = progress.completed_levels.iter().cloned().collect();
+    profile.unlocked_levels = progress.unlocked_levels.clone();
+    profile.personal_bests = progress.personal_bests.clone();
+    profile.collectibles_found = progress.collectibles_found.clone();
+    profile.skin = skin.0.clone();
+    profile.settings = settings.clone();
+    profile.bindings = bindings.clone();
+    active.save();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory per test, out of the way of the real saves
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("i_am_coin_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn old_profiles_migrate() {
+        for text in [
+            r#"(version: 0, completed_levels: ["first_steps"], skin: "copper")"#,
+            // from before there was a version at all
+            r#"(completed_levels: ["first_steps"], skin: "copper")"#,
+        ] {
+            let profile = parse_profile(text).unwrap();
+            assert_eq!(profile.version, SAVE_VERSION);
+            assert!(profile.completed_levels.contains("first_steps"), "lost the levels from {}", text);
+            assert_eq!(profile.skin, "copper");
+        }
+        assert!(matches!(parse_profile("(version: 99)"), Err(SaveError::TooNew(99))));
+    }
+
+    #[test]
+    fn progress_round_trips() {
+        let mut progress = Progress::default();
+        for time in [30.0, 25.0, 40.0] {
+            progress.record_completion("first_steps", time);
+        }
+        progress.collectibles_found.insert(crate::level::collectible_id("first_steps", 6));
+        let profile = SaveProfile{
+            completed_levels: progress.completed_levels.iter().cloned().collect(),
+            unlocked_levels: progress.unlocked_levels.clone(),
+            personal_bests: progress.personal_bests.clone(),
+            collectibles_found: progress.collectibles_found.clone(),
+            ..default()
+        };
+        let path = test_dir("round_trip").join("slot_0.ron");
+        save_profile(&path, &profile).unwrap();
+        let loaded = load_profile(&path).unwrap().unwrap();
+        assert_eq!(loaded, profile);
+        assert_eq!(loaded.personal_bests.get("first_steps"), Some(&25.0));
+        assert!(loaded.collectibles_found.contains("first_steps/6"));
+    }
+
+    #[test]
+    fn corrupt_profile_is_backed_up() {
+        let dir = test_dir("corrupt");
+        let path = dir.join("slot_0.ron");
+        let garbage = "(version: 1, completed_levels: [";
+        fs::write(&path, garbage).unwrap();
+        assert_eq!(load_or_default(&path), SaveProfile::default());
+        assert!(!path.exists(), "the broken file was left in place");
+        let backups: Vec<PathBuf> = fs::read_dir(&dir).unwrap()
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.to_string_lossy().contains(".corrupt-"))
+            .collect();
+        assert_eq!(backups.len(), 1, "expected one backup, found {:?}", backups);
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), garbage);
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::game_const::*;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Settings>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<SecondPlayerBindings>()
+            .add_systems(Update, (
+                apply_window_settings,
+                apply_light_settings,
+                apply_camera_settings,
+                apply_audio_settings,
+            ).run_if(resource_changed::<Settings>()))
+            // new lights and cameras should pick up the current settings too
+            .add_systems(Update, (
+                apply_light_settings.run_if(any_added::<PointLight>),
+                apply_camera_settings.run_if(any_added::<Projection>),
+            ))
+            ;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowModeSetting {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+/// Player facing options. Stored in the save profile.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub window_mode: WindowModeSetting,
+    pub vsync: bool,
+    pub shadows: bool,
+    /// Multiplier on top of `SENS_X` and `SENS_Y`.
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self{
+            window_mode: WindowModeSetting::Windowed,
+            vsync: true,
+            shadows: true,
+            mouse_sensitivity: 1.0,
+            invert_y: false,
+            fov: 45.0,
+            master_volume: 1.0,
+            music_volume: 0.8,
+            sfx_volume: 1.0,
+        }
+    }
+}
+
+/// Everything that can be changed from the settings screen.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SettingKind {
+    WindowMode,
+    Vsync,
+    Shadows,
+    MouseSensitivity,
+    InvertY,
+    Fov,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+}
+
+pub const SETTING_KINDS: [SettingKind; 9] = [
+    SettingKind::WindowMode,
+    SettingKind::Vsync,
+    SettingKind::Shadows,
+    SettingKind::MouseSensitivity,
+    SettingKind::InvertY,
+    SettingKind::Fov,
+    SettingKind::MasterVolume,
+    SettingKind::MusicVolume,
+    SettingKind::SfxVolume,
+];
+
+fn on_off(b: bool) -> &'static str {
+    if b { "On" } else { "Off" }
+}
+
+impl Settings {
+    pub fn mouse_scale(&self) -> Vec2 {
+        let invert = if self.invert_y { -1.0 } else { 1.0 };
+        Vec2::new(SENS_X, invert * SENS_Y) * self.mouse_sensitivity
+    }
+
+    /// Steps a setting up (`dir > 0`) or down.
+    pub fn adjust(&mut self, kind: SettingKind, dir: f32) {
+        match kind {
+            SettingKind::WindowMode => {
+                let modes = [WindowModeSetting::Windowed, WindowModeSetting::Borderless, WindowModeSetting::Fullscreen];
+                let current = modes.iter().position(|m| *m == self.window_mode).unwrap_or(0) as i32;
+                self.window_mode = modes[(current + dir.signum() as i32).rem_euclid(modes.len() as i32) as usize];
+            }
+            SettingKind::Vsync => self.vsync = !self.vsync,
+            SettingKind::Shadows => self.shadows = !self.shadows,
+            SettingKind::MouseSensitivity => self.mouse_sensitivity = (self.mouse_sensitivity + 0.1 * dir).clamp(0.1, 5.0),
+            SettingKind::InvertY => self.invert_y = !self.invert_y,
+            SettingKind::Fov => self.fov = (self.fov + 5.0 * dir).clamp(30.0, 110.0),
+            SettingKind::MasterVolume => self.master_volume = (self.master_volume + 0.1 * dir).clamp(0.0, 1.0),
+            SettingKind::MusicVolume => self.music_volume = (self.music_volume + 0.1 * dir).clamp(0.0, 1.0),
+            SettingKind::SfxVolume => self.sfx_volume = (self.sfx_volume + 0.1 * dir).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn describe(&self, kind: SettingKind) -> String {
+        match kind {
+            SettingKind::WindowMode => format!("Window: {:?}", self.window_mode),
+            SettingKind::Vsync => format!("VSync: {}", on_off(self.vsync)),
+            SettingKind::Shadows => format!("Shadows: {}", on_off(self.shadows)),
+            SettingKind::MouseSensitivity => format!("Mouse sensitivity: {:.1}", self.mouse_sensitivity),
+            SettingKind::InvertY => format!("Invert Y: {}", on_off(self.invert_y)),
+            SettingKind::Fov => format!("FOV: {:.0}", self.fov),
+            SettingKind::MasterVolume => format!("Master volume: {:.0}%", self.master_volume * 100.0),
+            SettingKind::MusicVolume => format!("Music volume: {:.0}%", self.music_volume * 100.0),
+            SettingKind::SfxVolume => format!("Effects volume: {:.0}%", self.sfx_volume * 100.0),
+        }
+    }
+}
+
+fn any_added<T: Component>(query: Query<(), Added<T>>) -> bool {
+    !query.is_empty()
+}
+
+fn apply_window_settings(
+    settings: Res<Settings>,
+    mut windows: Query<&mut Window, With<bevy::window::PrimaryWindow>>,
+) {
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    window.mode = match settings.window_mode {
+        WindowModeSetting::Windowed => bevy::window::WindowMode::Windowed,
+        WindowModeSetting::Borderless => bevy::window::WindowMode::BorderlessFullscreen,
+        WindowModeSetting::Fullscreen => bevy::window::WindowMode::Fullscreen,
+    };
+    window.present_mode = if settings.vsync {
+        bevy::window::PresentMode::AutoVsync
+    } else {
+        bevy::window::PresentMode::AutoNoVsync
+    };
+}
+
+fn apply_light_settings(
+    settings: Res<Settings>,
+    mut lights: Query<&mut PointLight>,
+) {
+    for mut light in lights.iter_mut() {
+        light.shadows_enabled = settings.shadows;
+    }
+}
+
+fn apply_camera_settings(
+    settings: Res<Settings>,
+    mut projections: Query<&mut Projection, With<Camera3d>>,
+) {
+    for mut projection in projections.iter_mut() {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = settings.fov.to_radians();
+        }
+    }
+}
+
+/// Only affects sounds started from now on, already playing ones are
+/// updated by whoever owns them.
+fn apply_audio_settings(
+    settings: Res<Settings>,
+    mut global_volume: ResMut<GlobalVolume>,
+) {
+    *global_volume = GlobalVolume::new(settings.master_volume);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Action {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Jump,
+    Reset,
+    CameraMode,
+    FlyDown,
+    DebugOverlay,
+    SwapCoin,
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyBindings(pub BTreeMap<Action, Vec<KeyCode>>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            (Action::Forward, vec![KeyCode::W, KeyCode::Up]),
+            (Action::Back, vec![KeyCode::S, KeyCode::Down]),
+            (Action::Left, vec![KeyCode::A, KeyCode::Left]),
+            (Action::Right, vec![KeyCode::D, KeyCode::Right]),
+            (Action::Jump, vec![KeyCode::Space]),
+            (Action::Reset, vec![KeyCode::R]),
+            (Action::CameraMode, vec![KeyCode::C]),
+            (Action::FlyDown, vec![KeyCode::ShiftLeft]),
+            (Action::DebugOverlay, vec![KeyCode::F3]),
+            (Action::SwapCoin, vec![KeyCode::Tab]),
+        ]))
+    }
+}
+
+impl KeyBindings {
+    /// Bindings saved before an action existed don't have it, give those the default keys.
+    pub fn with_missing_defaults(mut self) -> Self {
+        for (action, keys) in KeyBindings::default().0 {
+            self.0.entry(action).or_insert(keys);
+        }
+        self
+    }
+
+    /// The same bindings minus the given keys, for when another seat owns them.
+    pub fn without_keys(&self, taken: &[KeyCode]) -> Self {
+        Self(self.0.iter()
+            .map(|(action, keys)| (*action, keys.iter().copied().filter(|key| !taken.contains(key)).collect()))
+            .collect())
+    }
+
+    pub fn all_keys(&self) -> Vec<KeyCode> {
+        self.0.values().flatten().copied().collect()
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.0.get(&action).map(|keys| keys.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn pressed(&self, action: Action, input: &Input<KeyCode>) -> bool {
+        input.any_pressed(self.keys(action).iter().copied())
+    }
+
+    pub fn just_pressed(&self, action: Action, input: &Input<KeyCode>) -> bool {
+        input.any_just_pressed(self.keys(action).iter().copied())
+    }
+
+    pub fn just_released(&self, action: Action, input: &Input<KeyCode>) -> bool {
+        self.keys(action).iter().any(|key| input.just_released(*key))
+    }
+}
+
+/// The second seat in split screen, on the right side of the keyboard. While
+/// it plays, the first seat loses these keys.
+#[derive(Resource, Clone, Debug)]
+pub struct SecondPlayerBindings(pub KeyBindings);
+
+impl Default for SecondPlayerBindings {
+    fn default() -> Self {
+        Self(KeyBindings(BTreeMap::from([
+            (Action::Forward, vec![KeyCode::Up]),
+            (Action::Back, vec![KeyCode::Down]),
+            (Action::Left, vec![KeyCode::Left]),
+            (Action::Right, vec![KeyCode::Right]),
+            (Action::Jump, vec![KeyCode::ShiftRight]),
+            (Action::Reset, vec![KeyCode::Back]),
+        ])))
+    }
+}
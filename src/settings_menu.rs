@@ -0,0 +1,136 @@
+//! The settings screen. Opened from the main menu or the pause menu and drawn
+//! on top of them. Every change goes straight into `Settings`, which applies
+//! it live and gets written to the save profile.
+
+use bevy::prelude::*;
+use bevy::ui::FocusPolicy;
+
+use crate::AppState;
+use crate::menu::{menu_button, text_style, despawn_with};
+use crate::settings::{Settings, SettingKind, SETTING_KINDS};
+
+pub struct SettingsMenuPlugin;
+
+impl Plugin for SettingsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                open_settings,
+                settings_actions,
+                settings_labels,
+            ).run_if(in_state(AppState::Menu).or_else(in_state(AppState::Paused))))
+            .add_systems(OnEnter(AppState::InGame), despawn_with::<SettingsRoot>)
+            ;
+    }
+}
+
+const SETTINGS_BG: Color = Color::rgba(0.05, 0.05, 0.05, 0.95);
+
+/// Put this on any button that should open the settings screen.
+#[derive(Component)]
+pub struct OpenSettingsButton;
+
+#[derive(Component)]
+struct SettingsRoot;
+
+#[derive(Component)]
+enum SettingsButton {
+    Adjust(SettingKind, f32),
+    Back,
+}
+
+#[derive(Component)]
+struct SettingLabel(SettingKind);
+
+fn small_button(parent: &mut ChildBuilder, asset_server: &AssetServer, marker: impl Component, label: &str) {
+    parent.spawn((marker, ButtonBundle {
+        style: Style {
+            width: Val::Px(40.0),
+            height: Val::Px(36.0),
+            margin: UiRect::all(Val::Px(4.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: crate::menu::NORMAL_BUTTON.into(),
+        ..default()
+    })).with_children(|button| {
+        button.spawn(TextBundle::from_section(label, text_style(asset_server, 24.0)));
+    });
+}
+
+fn open_settings(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    buttons: Query<&Interaction, (Changed<Interaction>, With<OpenSettingsButton>)>,
+    open: Query<(), With<SettingsRoot>>,
+) {
+    if !buttons.iter().any(|interaction| *interaction == Interaction::Pressed) || !open.is_empty() {
+        return;
+    }
+    commands.spawn((SettingsRoot, NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: SETTINGS_BG.into(),
+        z_index: ZIndex::Global(10),
+        // or clicks on the backdrop land on the menu buttons underneath
+        focus_policy: FocusPolicy::Block,
+        ..default()
+    })).with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Settings", text_style(&asset_server, 48.0)));
+        for kind in SETTING_KINDS {
+            parent.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                small_button(row, &asset_server, SettingsButton::Adjust(kind, -1.0), "<");
+                row.spawn((SettingLabel(kind), TextBundle::from_section("", text_style(&asset_server, 22.0)).with_style(Style {
+                    width: Val::Px(320.0),
+                    ..default()
+                })));
+                small_button(row, &asset_server, SettingsButton::Adjust(kind, 1.0), ">");
+            });
+        }
+        menu_button(parent, &asset_server, SettingsButton::Back, "Back");
+    });
+}
+
+fn settings_actions(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &SettingsButton), Changed<Interaction>>,
+    roots: Query<Entity, With<SettingsRoot>>,
+    mut settings: ResMut<Settings>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            SettingsButton::Adjust(kind, dir) => settings.adjust(*kind, *dir),
+            SettingsButton::Back => {
+                for root in roots.iter() {
+                    commands.entity(root).despawn_recursive();
+                }
+            }
+        }
+    }
+}
+
+fn settings_labels(
+    settings: Res<Settings>,
+    mut labels: Query<(&SettingLabel, &mut Text)>,
+) {
+    for (label, mut text) in labels.iter_mut() {
+        text.sections[0].value = settings.describe(label.0);
+    }
+}
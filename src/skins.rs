@@ -0,0 +1,184 @@
+//! Coin skins. A skin only changes the shared coin materials from
+//! `CoinAssets`, so swapping it restyles the coin that's already spawned.
+
+use bevy::prelude::*;
+
+use crate::coin::CoinAssets;
+use crate::progress::Progress;
+
+pub struct SkinPlugin;
+
+impl Plugin for SkinPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SelectedSkin>()
+            .add_systems(Update, skin_changed.run_if(resource_changed::<SelectedSkin>()))
+            ;
+    }
+}
+
+pub enum SkinUnlock {
+    Always,
+    Collectibles(usize),
+    LevelComplete(&'static str),
+    /// Generated courses finished, see `Progress::courses_completed`.
+    Courses(usize),
+}
+
+pub struct CoinSkin {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub rim_color: Color,
+    pub face_tint: Color,
+    pub heads_texture: &'static str,
+    pub tails_texture: &'static str,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub unlock: SkinUnlock,
+}
+
+impl CoinSkin {
+    pub fn is_unlocked(&self, progress: &Progress) -> bool {
+        match self.unlock {
+            SkinUnlock::Always => true,
+            SkinUnlock::Collectibles(count) => progress.collectibles_found.len() >= count,
+            SkinUnlock::LevelComplete(level) => progress.has_completed(level),
+            SkinUnlock::Courses(count) => progress.courses_completed() >= count,
+        }
+    }
+
+    pub fn unlock_hint(&self) -> String {
+        match self.unlock {
+            SkinUnlock::Always => String::new(),
+            SkinUnlock::Collectibles(count) => format!("collect {}", count),
+            SkinUnlock::LevelComplete(level) => format!("finish {}", level),
+            SkinUnlock::Courses(count) => format!("finish {} courses", count),
+        }
+    }
+}
+
+pub const SKINS: &[CoinSkin] = &[
+    CoinSkin {
+        id: "classic",
+        name: "Classic",
+        rim_color: Color::rgb(0.8, 0.7, 0.6),
+        face_tint: Color::WHITE,
+        heads_texture: "coin_test0.PNG",
+        tails_texture: "coin_test1.PNG",
+        metallic: 0.6,
+        roughness: 0.5,
+        unlock: SkinUnlock::Always,
+    },
+    CoinSkin {
+        id: "copper",
+        name: "Copper",
+        rim_color: Color::rgb(0.72, 0.45, 0.2),
+        face_tint: Color::rgb(0.9, 0.6, 0.4),
+        heads_texture: "coin_test0.PNG",
+        tails_texture: "coin_test1.PNG",
+        metallic: 0.9,
+        roughness: 0.45,
+        unlock: SkinUnlock::LevelComplete("first_steps"),
+    },
+    CoinSkin {
+        id: "silver",
+        name: "Silver",
+        rim_color: Color::rgb(0.75, 0.75, 0.78),
+        face_tint: Color::rgb(0.85, 0.85, 0.9),
+        heads_texture: "coin_test0.PNG",
+        tails_texture: "coin_test1.PNG",
+        metallic: 1.0,
+        roughness: 0.25,
+        unlock: SkinUnlock::Collectibles(3),
+    },
+    CoinSkin {
+        id: "gold",
+        name: "Gold",
+        rim_color: Color::rgb(1.0, 0.78, 0.25),
+        face_tint: Color::rgb(1.0, 0.85, 0.4),
+        heads_texture: "coin_test0.PNG",
+        tails_texture: "coin_test1.PNG",
+        metallic: 1.0,
+        roughness: 0.2,
+        unlock: SkinUnlock::Courses(5),
+    },
+];
+
+pub fn skin_by_id(id: &str) -> &'static CoinSkin {
+    SKINS.iter().find(|skin| skin.id == id).unwrap_or(&SKINS[0])
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct SelectedSkin(pub String);
+
+impl Default for SelectedSkin {
+    fn default() -> Self {
+        Self(SKINS[0].id.to_string())
+    }
+}
+
+impl SelectedSkin {
+    /// The next unlocked skin after the current one, wrapping around.
+    pub fn next_unlocked(&self, progress: &Progress) -> &'static CoinSkin {
+        let current = SKINS.iter().position(|skin| skin.id == self.0).unwrap_or(0);
+        (1..=SKINS.len())
+            .map(|offset| &SKINS[(current + offset) % SKINS.len()])
+            .find(|skin| skin.is_unlocked(progress))
+            .unwrap_or(&SKINS[0])
+    }
+}
+
+pub fn apply_skin(
+    skin: &CoinSkin,
+    coin_assets: &CoinAssets,
+    materials: &mut Assets<StandardMaterial>,
+    asset_server: &AssetServer,
+) {
+    if let Some(rim) = materials.get_mut(&coin_assets.rim) {
+        rim.base_color = skin.rim_color;
+        rim.metallic = skin.metallic;
+        rim.perceptual_roughness = skin.roughness;
+    }
+    for (handle, texture) in [(&coin_assets.heads, skin.heads_texture), (&coin_assets.tails, skin.tails_texture)] {
+        if let Some(face) = materials.get_mut(handle) {
+            face.base_color = skin.face_tint;
+            face.base_color_texture = Some(asset_server.load(texture));
+            face.metallic = skin.metallic;
+            face.perceptual_roughness = skin.roughness;
+        }
+    }
+}
+
+fn skin_changed(
+    selected: Res<SelectedSkin>,
+    coin_assets: Res<CoinAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    apply_skin(skin_by_id(&selected.0), &coin_assets, &mut materials, &asset_server);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{load_level_file, PieceKind, LEVEL_DIR};
+
+    #[test]
+    fn every_skin_can_be_unlocked() {
+        let shipped: Vec<_> = std::fs::read_dir(LEVEL_DIR).unwrap()
+            .filter_map(|entry| entry.ok()?.path().file_stem()?.to_str().map(str::to_string))
+            .map(|name| load_level_file(&name).unwrap())
+            .collect();
+        let collectibles = shipped.iter()
+            .flat_map(|level| level.pieces.iter())
+            .filter(|piece| piece.kind == PieceKind::Collectible)
+            .count();
+        for skin in SKINS {
+            match skin.unlock {
+                SkinUnlock::Always | SkinUnlock::Courses(_) => {}
+                SkinUnlock::Collectibles(count) => assert!(count <= collectibles, "{} needs {} collectibles, the levels have {}", skin.id, count, collectibles),
+                SkinUnlock::LevelComplete(level) => assert!(shipped.iter().any(|l| l.name == level), "{} needs {}, which isn't a level", skin.id, level),
+            }
+        }
+    }
+}
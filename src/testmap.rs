@@ -1,19 +1,20 @@
 
 use bevy::prelude::*;
-use bevy_rapier3d::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use crate::game_const::{CUBOID_DEPTH, CUBOID_SIZE};
+use crate::level_logic::*;
+use crate::player::sensor;
 
 pub struct TestMapPlugin;  
 
 impl Plugin for TestMapPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_startup_system(testmap_setup)
+            .add_systems(Startup, testmap_setup)
             ;
     }
 }
 
-const CUBOID_DEPTH: f32 = 0.2;
-const CUBOID_SIZE: f32 = 10.0;
 
 
 const FLOOR_WIDTH: f32 = 10.0;
@@ -39,8 +40,8 @@ macro_rules! m_spawn_cuboid_wall_x {
             transform: Transform::from_translation(CUBOID_SIZE*$vec3),
             ..default()
         })
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid($vec2.x*CUBOID_SIZE/2.0, $vec2.y*CUBOID_SIZE/2.0, CUBOID_DEPTH/2.0));
+        .insert(RigidBody::Static)
+        .insert(Collider::cuboid($vec2.x*CUBOID_SIZE, $vec2.y*CUBOID_SIZE, CUBOID_DEPTH));
     };
 }
 
@@ -52,8 +53,8 @@ macro_rules! m_spawn_cuboid_wall_z {
             transform: Transform::from_translation(CUBOID_SIZE*$vec3),
             ..default()
         })
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid(CUBOID_DEPTH/2.0, $vec2.y*CUBOID_SIZE/2.0, $vec2.x*CUBOID_SIZE/2.0));
+        .insert(RigidBody::Static)
+        .insert(Collider::cuboid(CUBOID_DEPTH, $vec2.y*CUBOID_SIZE, $vec2.x*CUBOID_SIZE));
     };
 }
 
@@ -65,8 +66,8 @@ macro_rules! m_spawn_cuboid_floor {
             transform: Transform::from_translation(CUBOID_SIZE*$vec3),
             ..default()
         })
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid($vec2.x*CUBOID_SIZE/2.0, $floor_size*CUBOID_DEPTH/2.0, $vec2.y*CUBOID_SIZE/2.0));
+        .insert(RigidBody::Static)
+        .insert(Collider::cuboid($vec2.x*CUBOID_SIZE, $floor_size*CUBOID_DEPTH, $vec2.y*CUBOID_SIZE));
     };
 }
 
@@ -78,8 +79,48 @@ macro_rules! m_spawn_cuboid {
             transform: Transform::from_translation(CUBOID_SIZE*$pos + CUBOID_DEPTH*Vec3::new(0.0,0.5*$size.y,0.0)),
             ..default()
         })
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid($size.x*CUBOID_SIZE/2.0, $size.y*CUBOID_SIZE/2.0, $size.z*CUBOID_SIZE/2.0));
+        .insert(RigidBody::Static)
+        .insert(Collider::cuboid($size.x*CUBOID_SIZE, $size.y*CUBOID_SIZE, $size.z*CUBOID_SIZE));
+    };
+}
+
+// a floor slab tilted around x by $angle radians, rising towards -z
+macro_rules! m_spawn_ramp {
+    ($vec2:expr, $vec3:expr, $angle:expr, $commands:expr, $meshes:expr, $materials:expr, $color:expr) => {
+        $commands.spawn(PbrBundle {
+            mesh: $meshes.add(shape::Box::new($vec2.x*CUBOID_SIZE, CUBOID_DEPTH, $vec2.y*CUBOID_SIZE).into()),
+            material: $materials.add($color.into()),
+            transform: Transform::from_translation(CUBOID_SIZE*$vec3).with_rotation(Quat::from_rotation_x($angle)),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(Collider::cuboid($vec2.x*CUBOID_SIZE, CUBOID_DEPTH, $vec2.y*CUBOID_SIZE));
+    };
+}
+
+// an arc of $segments tilted slabs curving up towards -z, like a quarter pipe.
+// $pos is the bottom of the arc, $span how far it bends in radians
+macro_rules! m_spawn_curve {
+    ($width:expr, $radius:expr, $span:expr, $segments:expr, $pos:expr, $commands:expr, $meshes:expr, $materials:expr, $color:expr) => {
+        let radius = $radius*CUBOID_SIZE;
+        let step = $span / $segments as f32;
+        // a bit longer than the chord so the pieces overlap without gaps
+        let length = 2.0*radius*(0.5*step).sin() + CUBOID_DEPTH;
+        let mesh = $meshes.add(shape::Box::new($width*CUBOID_SIZE, CUBOID_DEPTH, length).into());
+        let material = $materials.add($color.into());
+        for i in 0..$segments {
+            let angle = (i as f32 + 0.5)*step;
+            let rotation = Quat::from_rotation_x(angle);
+            let on_arc = Vec3::new(0.0, radius*(1.0 - angle.cos()), -radius*angle.sin());
+            $commands.spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(CUBOID_SIZE*$pos + on_arc - rotation*Vec3::Y*0.5*CUBOID_DEPTH).with_rotation(rotation),
+                ..default()
+            })
+            .insert(RigidBody::Static)
+            .insert(Collider::cuboid($width*CUBOID_SIZE, CUBOID_DEPTH, length));
+        }
     };
 }
 
@@ -91,9 +132,36 @@ macro_rules! m_spawn_sticky_field {
             transform: Transform::from_translation(CUBOID_SIZE*$pos + CUBOID_DEPTH*Vec3::new(0.0,0.5,0.0)),
             ..default()
         }))
-        .insert(RigidBody::Fixed)
-        .insert(Collider::cuboid($size.x*CUBOID_SIZE/2.0, $size.y*CUBOID_SIZE/2.0, $size.z*CUBOID_SIZE/2.0))
-        .insert(ActiveEvents::COLLISION_EVENTS);
+        .insert(RigidBody::Static)
+        .insert(Collider::cuboid($size.x*CUBOID_SIZE, $size.y*CUBOID_SIZE, $size.z*CUBOID_SIZE));
+    };
+}
+
+macro_rules! m_spawn_door_z {
+    ($vec2:expr, $vec3:expr, $open:expr, $signal:expr, $commands:expr, $meshes:expr, $materials:expr, $color:expr) => {
+        $commands.spawn((Door { signal: $signal, closed: CUBOID_SIZE*$vec3, open_offset: CUBOID_SIZE*$open, speed: 8.0 }, PbrBundle {
+            mesh: $meshes.add(shape::Box::new(CUBOID_DEPTH, $vec2.y*CUBOID_SIZE, $vec2.x*CUBOID_SIZE).into()),
+            material: $materials.add($color.into()),
+            transform: Transform::from_translation(CUBOID_SIZE*$vec3),
+            ..default()
+        }))
+        .insert(RigidBody::Kinematic)
+        .insert(Position(CUBOID_SIZE*$vec3))
+        .insert(Collider::cuboid(CUBOID_DEPTH, $vec2.y*CUBOID_SIZE, $vec2.x*CUBOID_SIZE));
+    };
+}
+
+macro_rules! m_spawn_pressure_plate {
+    ($plate:expr, $size:expr, $pos:expr, $commands:expr, $meshes:expr, $materials:expr, $color:expr) => {
+        $commands.spawn(($plate, PbrBundle {
+            mesh: $meshes.add(shape::Box::new($size.x*CUBOID_SIZE, $size.y*CUBOID_SIZE, $size.z*CUBOID_SIZE).into()),
+            material: $materials.add($color.into()),
+            transform: Transform::from_translation(CUBOID_SIZE*$pos + CUBOID_DEPTH*Vec3::new(0.0,0.5,0.0)),
+            ..default()
+        }))
+        .insert(RigidBody::Static)
+        .insert(sensor())
+        .insert(Collider::cuboid($size.x*CUBOID_SIZE, $size.y*CUBOID_SIZE, $size.z*CUBOID_SIZE));
     };
 }
 
@@ -131,6 +199,12 @@ fn testmap_setup(
     m_spawn_cuboid_floor!(Vec2::new(0.5,0.5),Vec3::new(2.0,0.9,1.4), commands, meshes, materials, RED_LIGHT, 1.0);
 
 
+    // ramp and quarter pipe along the west side of the main floor
+    const RAMP_ANGLE: f32 = 0.35;
+    m_spawn_ramp!(Vec2::new(0.8,1.0), Vec3::new(-1.5,0.1+0.5*RAMP_ANGLE.sin(),-6.0), RAMP_ANGLE, commands, meshes, materials, GREEN_DARK);
+    m_spawn_curve!(0.8, 0.6, std::f32::consts::FRAC_PI_2, 12, Vec3::new(-1.5,0.1,-9.0), commands, meshes, materials, GREEN_DARK);
+
+
     const SPEC_CUBE: f32 = 0.2;
     m_spawn_cuboid!(Vec3::new(4.9,SPEC_CUBE,0.4), Vec3::new(0.0,SPEC_CUBE/2.0+0.11,-25.0), commands, meshes, materials, BLUE_DARK);
 
@@ -174,21 +248,29 @@ fn testmap_setup(
     // main floor
     m_spawn_cuboid_floor!(Vec2::new(5.0,5.0),Vec3::new(0.0,-0.1,0.0)+FIFTH_ROOM_OFFSET, commands, meshes, materials, BLACK, 10.0);
     // main four walls
-    m_spawn_cuboid_wall_x!(Vec2::new(4.97,3.0), Vec3::new(0.015,0.0,-2.5)+FIFTH_ROOM_OFFSET, commands, meshes, materials, GREEN_LIGHT);
+    m_spawn_cuboid_wall_x!(Vec2::new(5.0,3.0), Vec3::new(0.0,0.0,-2.5)+FIFTH_ROOM_OFFSET, commands, meshes, materials, GREEN_LIGHT);
     m_spawn_cuboid_wall_x!(Vec2::new(5.0,3.0), Vec3::new(0.0,0.0,2.5)+FIFTH_ROOM_OFFSET, commands, meshes, materials, GREEN_LIGHT);
-    m_spawn_cuboid_wall_z!(Vec2::new(5.0,3.0), Vec3::new(-2.5,0.0,0.0)+FIFTH_ROOM_OFFSET, commands, meshes, materials, GREEN_LIGHT);
+    // west wall is shared with the secret room and has the hidden door at its north end
+    m_spawn_cuboid_wall_z!(Vec2::new(4.0,3.0), Vec3::new(-2.5,0.0,0.5)+FIFTH_ROOM_OFFSET, commands, meshes, materials, GREEN_LIGHT);
+    m_spawn_door_z!(Vec2::new(1.0,3.0), Vec3::new(-2.5,0.0,-2.0)+FIFTH_ROOM_OFFSET, Vec3::new(0.0,3.0,0.0), SECRET_DOOR, commands, meshes, materials, GREEN_LIGHT);
     //m_spawn_cuboid_wall_z!(Vec2::new(5.0,3.0), Vec3::new(2.5,0.0,0.0)+FIFTH_ROOM_OFFSET, commands, meshes, materials, GREEN_LIGHT);
-    
+
+    // hidden door: land tails up on the plate and the door stays open for a while
+    const SECRET_PLATE: SignalId = 1;
+    const SECRET_DOOR: SignalId = 2;
+    const PLATE_HEIGHT: f32 = 0.01;
+    m_spawn_pressure_plate!(PressurePlate::new(SECRET_PLATE).with_face(PlateFace::Tails), Vec3::new(0.3,PLATE_HEIGHT,0.3), Vec3::new(1.8,0.5*PLATE_HEIGHT,1.8)+FIFTH_ROOM_OFFSET, commands, meshes, materials, RED_LIGHT);
+    commands.spawn(LogicNode::new(LogicKind::Timer { duration: 5.0 }, vec![SECRET_PLATE], SECRET_DOOR));
+
     // Secret room
-    const SECRET_ROOM_OFFSET: Vec3 = Vec3::new(-4.96,0.0,-5.0);
+    const SECRET_ROOM_OFFSET: Vec3 = Vec3::new(-5.0,0.0,0.0);
     let secret_room_offset = SECRET_ROOM_OFFSET+FIFTH_ROOM_OFFSET;
     // main floor
     m_spawn_cuboid_floor!(Vec2::new(5.0,5.0),Vec3::new(0.0,0.0,0.0)+secret_room_offset, commands, meshes, materials, FULL_BLACK, 1.0);
-    // main four walls
+    // main three walls, the east one is the fifth room's west wall
     m_spawn_cuboid_wall_x!(Vec2::new(5.0,3.0), Vec3::new(0.0,0.0,-2.5)+secret_room_offset, commands, meshes, materials, FULL_BLACK);
-    m_spawn_cuboid_wall_x!(Vec2::new(4.98,3.0), Vec3::new(-0.02,0.0,2.5)+secret_room_offset, commands, meshes, materials, FULL_BLACK);
+    m_spawn_cuboid_wall_x!(Vec2::new(5.0,3.0), Vec3::new(0.0,0.0,2.5)+secret_room_offset, commands, meshes, materials, FULL_BLACK);
     m_spawn_cuboid_wall_z!(Vec2::new(5.0,3.0), Vec3::new(-2.5,0.0,0.0)+secret_room_offset, commands, meshes, materials, FULL_BLACK);
-    m_spawn_cuboid_wall_z!(Vec2::new(5.0,3.0), Vec3::new(2.5,0.0,0.0)+secret_room_offset, commands, meshes, materials, FULL_BLACK);
     // roof
     m_spawn_cuboid_floor!(Vec2::new(5.0,5.0),Vec3::new(0.0,1.3,0.0)+secret_room_offset, commands, meshes, materials, FULL_BLACK, 1.0);
 